@@ -2,11 +2,37 @@ use serde::{Deserialize, Serialize};
 
 use crate::document::Color;
 
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum Fit {
+    #[default]
+    Fill,
+    Fit,
+    Stretch,
+    Tile,
+}
+
+impl Fit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fill" => Some(Fit::Fill),
+            "fit" => Some(Fit::Fit),
+            "stretch" => Some(Fit::Stretch),
+            "tile" => Some(Fit::Tile),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Canvas {
     pub width: u32,
     pub height: u32,
     pub background: Color,
+    #[serde(default)]
+    pub background_image: Option<String>,
+    #[serde(default)]
+    pub background_fit: Fit,
 }
 
 impl Canvas {
@@ -15,6 +41,8 @@ impl Canvas {
             width,
             height,
             background: Color::new(1.0, 1.0, 1.0, 1.0),
+            background_image: None,
+            background_fit: Fit::Fill,
         }
     }
 }
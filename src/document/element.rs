@@ -1,8 +1,81 @@
 use serde::{Deserialize, Serialize};
 
-use crate::elements::{ElementData, ImageElement, ShapeElement, TextElement};
+use crate::elements::{
+    ElementData, Fill, ImageElement, ShapeElement, ShapeType, Stroke, TextAlign, TextElement,
+};
 
-use super::{Color, Transform2D};
+use super::{Bounds, Color, Transform2D};
+
+const LINE_HIT_TOLERANCE: f32 = 3.0;
+
+// How far a line's stroke reaches from its center; an unstroked line draws as a hairline.
+pub fn line_reach(stroke_width: f32) -> f32 {
+    stroke_width.max(1.0) * 0.5
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Shadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub blur: f32,
+    pub color: Color,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            offset_x: 0.0,
+            offset_y: 4.0,
+            blur: 8.0,
+            color: Color::new(0.0, 0.0, 0.0, 0.35),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top_left" => Some(Anchor::TopLeft),
+            "top" => Some(Anchor::Top),
+            "top_right" => Some(Anchor::TopRight),
+            "left" => Some(Anchor::Left),
+            "center" => Some(Anchor::Center),
+            "right" => Some(Anchor::Right),
+            "bottom_left" => Some(Anchor::BottomLeft),
+            "bottom" => Some(Anchor::Bottom),
+            "bottom_right" => Some(Anchor::BottomRight),
+            _ => None,
+        }
+    }
+
+    pub fn factors(self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Element {
@@ -10,6 +83,24 @@ pub struct Element {
     pub name: String,
     pub transform: Transform2D,
     pub data: ElementData,
+    #[serde(default)]
+    pub shadow: Option<Shadow>,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    #[serde(default)]
+    pub anchor: Anchor,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
 }
 
 impl Element {
@@ -19,6 +110,69 @@ impl Element {
             name: name.into(),
             transform,
             data,
+            shadow: None,
+            locked: false,
+            visible: true,
+            anchor: Anchor::TopLeft,
+            opacity: 1.0,
+        }
+    }
+
+    pub fn visual_bounds(&self) -> Bounds {
+        let mut bounds = self.transform.bounds();
+        if let Some(reach) = self.line_stroke_reach() {
+            bounds = Bounds {
+                x: bounds.x - reach,
+                y: bounds.y - reach,
+                width: bounds.width + reach * 2.0,
+                height: bounds.height + reach * 2.0,
+            };
+        }
+        match self.shadow {
+            Some(shadow) => {
+                let spread = shadow.blur * 0.5;
+                bounds.union(&Bounds {
+                    x: bounds.x + shadow.offset_x - spread,
+                    y: bounds.y + shadow.offset_y - spread,
+                    width: bounds.width + spread * 2.0,
+                    height: bounds.height + spread * 2.0,
+                })
+            }
+            None => bounds,
+        }
+    }
+
+    pub fn contains_point(&self, x: f32, y: f32) -> bool {
+        if let Some(reach) = self.line_stroke_reach() {
+            let [(ax, ay), (bx, by)] = self.transform.line_endpoints();
+            let (dx, dy) = (bx - ax, by - ay);
+            let length_sq = dx * dx + dy * dy;
+            let t = if length_sq > 0.0 {
+                (((x - ax) * dx + (y - ay) * dy) / length_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (px, py) = (ax + dx * t - x, ay + dy * t - y);
+            return (px * px + py * py).sqrt() <= reach.max(LINE_HIT_TOLERANCE);
+        }
+        match &self.data {
+            ElementData::Shape(shape) if matches!(shape.shape_type, ShapeType::Ellipse) => {
+                let (lx, ly) = self.transform.local_point(x, y);
+                let nx = lx / (self.transform.width * 0.5).max(f32::EPSILON);
+                let ny = ly / (self.transform.height * 0.5).max(f32::EPSILON);
+                nx * nx + ny * ny <= 1.0
+            }
+            ElementData::Group(_) => false,
+            _ => self.transform.contains_point(x, y),
+        }
+    }
+
+    fn line_stroke_reach(&self) -> Option<f32> {
+        match &self.data {
+            ElementData::Shape(shape) if matches!(shape.shape_type, ShapeType::Line) => {
+                Some(line_reach(shape.stroke.map_or(0.0, |stroke| stroke.width)))
+            }
+            _ => None,
         }
     }
 
@@ -35,25 +189,127 @@ impl Element {
     }
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ElementUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub y: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub height: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub rotation: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pivot_x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pivot_y: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shape_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sides: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corner_radius: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub font_family: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub font_size: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_height: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub letter_spacing: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_stroke_color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_stroke_width: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fill: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_hex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_width: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_offset_x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_offset_y: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_blur: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image_source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image_brightness: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image_contrast: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image_saturation: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop_x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop_y: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop_width: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop_height: Option<f32>,
 }
 
 impl ElementUpdate {
+    pub fn is_finite(&self) -> bool {
+        let values = [
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+            self.rotation,
+            self.opacity,
+            self.pivot_x,
+            self.pivot_y,
+            self.corner_radius,
+            self.font_size,
+            self.line_height,
+            self.letter_spacing,
+            self.text_stroke_width,
+            self.stroke_width,
+            self.shadow_offset_x,
+            self.shadow_offset_y,
+            self.shadow_blur,
+            self.image_brightness,
+            self.image_contrast,
+            self.image_saturation,
+            self.crop_x,
+            self.crop_y,
+            self.crop_width,
+            self.crop_height,
+        ];
+        let colors = [
+            self.text_stroke_color,
+            self.fill,
+            self.stroke_color,
+            self.shadow_color,
+        ];
+        values.iter().flatten().all(|value| value.is_finite())
+            && colors.iter().flatten().all(|color| color.is_finite())
+    }
+
+    pub fn fill_color(&self) -> Option<Color> {
+        self.fill
+            .or_else(|| self.fill_hex.as_deref().and_then(|hex| Color::from_hex(hex).ok()))
+    }
+
     pub fn apply_to(&self, element: &mut Element) {
         if let Some(name) = &self.name {
             element.name = name.clone();
@@ -74,6 +330,66 @@ impl ElementUpdate {
         if let Some(rotation) = self.rotation {
             element.transform.rotation = rotation;
         }
+        if let Some(pivot_x) = self.pivot_x {
+            element.transform.pivot.0 = pivot_x.clamp(0.0, 1.0);
+        }
+        if let Some(pivot_y) = self.pivot_y {
+            element.transform.pivot.1 = pivot_y.clamp(0.0, 1.0);
+        }
+        if let Some(opacity) = self.opacity {
+            element.opacity = opacity.clamp(0.0, 1.0);
+        }
+
+        if self.shadow_enabled == Some(false) {
+            element.shadow = None;
+        } else if self.shadow_enabled == Some(true)
+            || self.shadow_offset_x.is_some()
+            || self.shadow_offset_y.is_some()
+            || self.shadow_blur.is_some()
+            || self.shadow_color.is_some()
+        {
+            let shadow = element.shadow.get_or_insert_with(Shadow::default);
+            if let Some(offset_x) = self.shadow_offset_x {
+                shadow.offset_x = offset_x;
+            }
+            if let Some(offset_y) = self.shadow_offset_y {
+                shadow.offset_y = offset_y;
+            }
+            if let Some(blur) = self.shadow_blur {
+                shadow.blur = blur.max(0.0);
+            }
+            if let Some(color) = self.shadow_color {
+                shadow.color = color;
+            }
+        }
+
+        if let ElementData::Shape(shape) = &mut element.data {
+            if let Some(shape_type) = self.shape_type.as_deref().and_then(ShapeType::from_name) {
+                shape.shape_type = shape_type;
+            }
+            if let Some(sides) = self.sides {
+                shape.sides = sides.max(3);
+            }
+            if let Some(corner_radius) = self.corner_radius {
+                shape.corner_radius = corner_radius.max(0.0);
+            }
+            if let Some(fill) = self.fill_color() {
+                shape.fill = Some(Fill { color: fill });
+            }
+            if self.stroke_color.is_some() || self.stroke_width.is_some() {
+                let mut stroke = shape.stroke.unwrap_or(Stroke {
+                    color: Color::new(0.0, 0.0, 0.0, 1.0),
+                    width: 1.0,
+                });
+                if let Some(color) = self.stroke_color {
+                    stroke.color = color;
+                }
+                if let Some(width) = self.stroke_width {
+                    stroke.width = width.max(0.0);
+                }
+                shape.stroke = if stroke.width > 0.0 { Some(stroke) } else { None };
+            }
+        }
 
         if let ElementData::Text(text) = &mut element.data {
             if let Some(content) = &self.content {
@@ -85,9 +401,31 @@ impl ElementUpdate {
             if let Some(font_size) = self.font_size {
                 text.font_size = font_size.max(1.0);
             }
-            if let Some(fill) = self.fill {
+            if let Some(align) = self.align.as_deref().and_then(TextAlign::from_name) {
+                text.align = align;
+            }
+            if let Some(line_height) = self.line_height {
+                text.line_height = line_height.max(0.1);
+            }
+            if let Some(letter_spacing) = self.letter_spacing {
+                text.letter_spacing = letter_spacing;
+            }
+            if let Some(fill) = self.fill_color() {
                 text.fill = fill;
             }
+            if self.text_stroke_color.is_some() || self.text_stroke_width.is_some() {
+                let mut stroke = text.stroke.unwrap_or(Stroke {
+                    color: Color::new(0.0, 0.0, 0.0, 1.0),
+                    width: 1.0,
+                });
+                if let Some(color) = self.text_stroke_color {
+                    stroke.color = color;
+                }
+                if let Some(width) = self.text_stroke_width {
+                    stroke.width = width.max(0.0);
+                }
+                text.stroke = if stroke.width > 0.0 { Some(stroke) } else { None };
+            }
         }
 
         if let ElementData::Image(image) = &mut element.data {
@@ -103,6 +441,66 @@ impl ElementUpdate {
             if let Some(saturation) = self.image_saturation {
                 image.filters.saturation = saturation.clamp(0.0, 2.0);
             }
+            if self.crop_x.is_some()
+                || self.crop_y.is_some()
+                || self.crop_width.is_some()
+                || self.crop_height.is_some()
+            {
+                let mut crop = image.crop.unwrap_or(Bounds {
+                    x: 0.0,
+                    y: 0.0,
+                    width: 1.0,
+                    height: 1.0,
+                });
+                crop.x = self.crop_x.unwrap_or(crop.x).clamp(0.0, 1.0);
+                crop.y = self.crop_y.unwrap_or(crop.y).clamp(0.0, 1.0);
+                crop.width = self.crop_width.unwrap_or(crop.width).min(1.0 - crop.x).max(0.0);
+                crop.height = self.crop_height.unwrap_or(crop.height).min(1.0 - crop.y).max(0.0);
+                image.crop = Some(crop);
+            }
+        }
+
+        if let ElementData::Instance(instance) = &mut element.data {
+            let overrides = &mut instance.overrides;
+            macro_rules! merge {
+                ($($field:ident),*) => {
+                    $(if self.$field.is_some() {
+                        overrides.$field = self.$field.clone();
+                    })*
+                };
+            }
+            merge!(
+                shape_type,
+                sides,
+                corner_radius,
+                content,
+                font_family,
+                font_size,
+                align,
+                line_height,
+                letter_spacing,
+                text_stroke_color,
+                text_stroke_width,
+                fill,
+                fill_hex,
+                stroke_color,
+                stroke_width,
+                image_source,
+                image_brightness,
+                image_contrast,
+                image_saturation,
+                crop_x,
+                crop_y,
+                crop_width,
+                crop_height
+            );
+            // fill_color prefers fill, so a new color in either form replaces the other.
+            if self.fill.is_some() && self.fill_hex.is_none() {
+                overrides.fill_hex = None;
+            }
+            if self.fill_hex.is_some() && self.fill.is_none() {
+                overrides.fill = None;
+            }
         }
     }
 }
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use super::Transform2D;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum GuideOrientation {
+    Horizontal,
+    Vertical,
+}
+
+impl GuideOrientation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "horizontal" => Some(GuideOrientation::Horizontal),
+            "vertical" => Some(GuideOrientation::Vertical),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Guide {
+    pub orientation: GuideOrientation,
+    pub position: f32,
+}
+
+impl Guide {
+    pub fn new(orientation: GuideOrientation, position: f32) -> Self {
+        Self {
+            orientation,
+            position,
+        }
+    }
+}
+
+fn snap_offset(edges: [f32; 3], targets: &[f32], threshold: f32) -> Option<(f32, f32)> {
+    let mut best = None;
+    let mut best_distance = threshold;
+    for &target in targets {
+        for edge in edges {
+            let distance = (target - edge).abs();
+            if distance <= best_distance {
+                best = Some((target - edge, target));
+                best_distance = distance;
+            }
+        }
+    }
+    best
+}
+
+pub fn snap_transform(
+    transform: Transform2D,
+    x_targets: &[f32],
+    y_targets: &[f32],
+    threshold: f32,
+) -> (Transform2D, Vec<Guide>) {
+    let bounds = transform.bounds();
+    let mut snapped = transform;
+    let mut lines = Vec::new();
+    let x_edges = [bounds.x, bounds.x + bounds.width * 0.5, bounds.x + bounds.width];
+    if let Some((offset, target)) = snap_offset(x_edges, x_targets, threshold) {
+        snapped.x += offset;
+        lines.push(Guide::new(GuideOrientation::Vertical, target));
+    }
+    let y_edges = [bounds.y, bounds.y + bounds.height * 0.5, bounds.y + bounds.height];
+    if let Some((offset, target)) = snap_offset(y_edges, y_targets, threshold) {
+        snapped.y += offset;
+        lines.push(Guide::new(GuideOrientation::Horizontal, target));
+    }
+    (snapped, lines)
+}
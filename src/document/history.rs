@@ -1,6 +1,9 @@
-use crate::document::{Document, Element};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+use crate::document::{Document, Element, Layer};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     AddElement {
         layer_id: u32,
@@ -18,6 +21,24 @@ pub enum Command {
         before: Element,
         after: Element,
     },
+    MoveElement {
+        element_id: u32,
+        from_layer_id: u32,
+        from_index: usize,
+        to_layer_id: u32,
+        to_index: usize,
+    },
+    AddSymbol {
+        master: Element,
+    },
+    RemoveLayer {
+        index: usize,
+        layer: Layer,
+        active_layer_id: u32,
+    },
+    Batch {
+        commands: Vec<Command>,
+    },
 }
 
 impl Command {
@@ -37,6 +58,23 @@ impl Command {
                 after,
                 ..
             } => document.replace_element_at(*layer_id, *index, after.clone()),
+            Command::MoveElement {
+                element_id,
+                to_layer_id,
+                to_index,
+                ..
+            } => document
+                .move_element(*element_id, *to_layer_id, Some(*to_index))
+                .is_some(),
+            Command::AddSymbol { master } => document.insert_symbol(master.clone()),
+            Command::RemoveLayer { layer, .. } => document.remove_layer(layer.id).is_some(),
+            Command::Batch { commands } => {
+                let mut changed = false;
+                for command in commands {
+                    changed |= command.apply(document);
+                }
+                changed
+            }
         }
     }
 
@@ -56,6 +94,78 @@ impl Command {
                 before,
                 ..
             } => document.replace_element_at(*layer_id, *index, before.clone()),
+            Command::MoveElement {
+                element_id,
+                from_layer_id,
+                from_index,
+                ..
+            } => document
+                .move_element(*element_id, *from_layer_id, Some(*from_index))
+                .is_some(),
+            Command::AddSymbol { master } => document.symbols.remove(&master.id).is_some(),
+            Command::RemoveLayer {
+                index,
+                layer,
+                active_layer_id,
+            } => document.restore_layer(*index, layer.clone(), *active_layer_id),
+            Command::Batch { commands } => {
+                let mut changed = false;
+                for command in commands.iter().rev() {
+                    changed |= command.undo(document);
+                }
+                changed
+            }
+        }
+    }
+
+    fn updates_same(&self, next: &Command) -> bool {
+        match (self, next) {
+            (
+                Command::UpdateElement { after, .. },
+                Command::UpdateElement { after: next, .. },
+            ) => after.id == next.id,
+            (Command::Batch { commands }, Command::Batch { commands: next }) => {
+                commands.len() == next.len()
+                    && commands
+                        .iter()
+                        .zip(next)
+                        .all(|(command, next)| command.updates_same(next))
+            }
+            _ => false,
+        }
+    }
+
+    // Folds a follow-up update of the same elements into this one, keeping the first `before`.
+    fn absorb(&mut self, next: &Command) -> bool {
+        if !self.updates_same(next) {
+            return false;
+        }
+        match (self, next) {
+            (Command::UpdateElement { after, .. }, Command::UpdateElement { after: next, .. }) => {
+                *after = next.clone();
+            }
+            (Command::Batch { commands }, Command::Batch { commands: next }) => {
+                for (command, next) in commands.iter_mut().zip(next) {
+                    command.absorb(next);
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum JournalEntry {
+    Apply(Command),
+    Revert(Command),
+}
+
+impl JournalEntry {
+    fn replay(&self, document: &mut Document) -> bool {
+        match self {
+            JournalEntry::Apply(command) => command.apply(document),
+            JournalEntry::Revert(command) => command.undo(document),
         }
     }
 }
@@ -63,6 +173,8 @@ impl Command {
 pub struct History {
     undo_stack: Vec<Command>,
     redo_stack: Vec<Command>,
+    coalesce_key: Option<String>,
+    journal: Vec<JournalEntry>,
 }
 
 impl History {
@@ -70,35 +182,82 @@ impl History {
         Self {
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
+            coalesce_key: None,
+            journal: Vec::new(),
         }
     }
 
     pub fn clear(&mut self) {
         self.undo_stack.clear();
         self.redo_stack.clear();
+        self.coalesce_key = None;
+        self.journal.clear();
+    }
+
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
+    pub fn replay(&mut self, entries: Vec<JournalEntry>, document: &mut Document) -> usize {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.coalesce_key = None;
+        let mut applied = 0;
+        for entry in entries {
+            if entry.replay(document) {
+                applied += 1;
+                self.journal.push(entry);
+            }
+        }
+        applied
     }
 
     pub fn record(&mut self, command: Command) {
+        self.journal.push(JournalEntry::Apply(command.clone()));
         self.undo_stack.push(command);
         self.redo_stack.clear();
+        self.coalesce_key = None;
     }
 
-    pub fn undo(&mut self, document: &mut Document) -> bool {
-        if let Some(command) = self.undo_stack.pop() {
-            if command.undo(document) {
-                self.redo_stack.push(command);
-                return true;
+    pub fn record_coalesced(&mut self, key: &str, command: Command) {
+        if self.coalesce_key.as_deref() == Some(key)
+            && let Some(last) = self.undo_stack.last_mut()
+            && last.absorb(&command)
+        {
+            if let Some(JournalEntry::Apply(entry)) = self.journal.last_mut() {
+                *entry = last.clone();
             }
+            self.redo_stack.clear();
+            return;
+        }
+        self.record(command);
+        self.coalesce_key = Some(key.to_string());
+    }
+
+    pub fn break_coalescing(&mut self) {
+        self.coalesce_key = None;
+    }
+
+    pub fn undo(&mut self, document: &mut Document) -> bool {
+        self.coalesce_key = None;
+        if let Some(command) = self.undo_stack.pop()
+            && command.undo(document)
+        {
+            self.journal.push(JournalEntry::Revert(command.clone()));
+            self.redo_stack.push(command);
+            return true;
         }
         false
     }
 
     pub fn redo(&mut self, document: &mut Document) -> bool {
-        if let Some(command) = self.redo_stack.pop() {
-            if command.apply(document) {
-                self.undo_stack.push(command);
-                return true;
-            }
+        self.coalesce_key = None;
+        if let Some(command) = self.redo_stack.pop()
+            && command.apply(document)
+        {
+            self.journal.push(JournalEntry::Apply(command.clone()));
+            self.undo_stack.push(command);
+            return true;
         }
         false
     }
@@ -2,15 +2,55 @@ use serde::{Deserialize, Serialize};
 
 use super::Element;
 
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Add,
+}
+
+impl BlendMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(BlendMode::Normal),
+            "multiply" => Some(BlendMode::Multiply),
+            "screen" => Some(BlendMode::Screen),
+            "add" => Some(BlendMode::Add),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Layer {
     pub id: u32,
     pub name: String,
     pub visible: bool,
     pub locked: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
     pub elements: Vec<Element>,
 }
 
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LayerSummary {
+    pub id: u32,
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    pub element_count: usize,
+}
+
 impl Layer {
     pub fn new(id: u32, name: impl Into<String>) -> Self {
         Self {
@@ -18,7 +58,21 @@ impl Layer {
             name: name.into(),
             visible: true,
             locked: false,
+            opacity: default_opacity(),
+            blend_mode: BlendMode::Normal,
             elements: Vec::new(),
         }
     }
+
+    pub fn summary(&self) -> LayerSummary {
+        LayerSummary {
+            id: self.id,
+            name: self.name.clone(),
+            visible: self.visible,
+            locked: self.locked,
+            opacity: self.opacity,
+            blend_mode: self.blend_mode,
+            element_count: self.elements.len(),
+        }
+    }
 }
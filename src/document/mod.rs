@@ -1,18 +1,23 @@
 mod canvas;
 mod element;
+mod guide;
 mod history;
 mod layer;
+mod outline;
 mod transform;
 
+use std::collections::{BTreeMap, HashSet};
+
 use serde::{Deserialize, Serialize};
 
-use crate::elements::{ElementData, ShapeElement};
+use crate::elements::{ElementData, GroupLayout, LayoutDirection, ShapeElement};
 
-pub use canvas::Canvas;
-pub use element::{Element, ElementUpdate};
-pub use history::{Command, History};
-pub use layer::Layer;
-pub use transform::Transform2D;
+pub use canvas::{Canvas, Fit};
+pub use element::{Anchor, Element, ElementUpdate, line_reach};
+pub use guide::{Guide, GuideOrientation};
+pub use history::{Command, History, JournalEntry};
+pub use layer::{BlendMode, Layer, LayerSummary};
+pub use transform::{Bounds, Transform2D};
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Color {
@@ -26,6 +31,71 @@ impl Color {
     pub fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
         Self { r, g, b, a }
     }
+
+    pub fn from_hex(value: &str) -> Result<Color, String> {
+        let digits = value.strip_prefix('#').unwrap_or(value);
+        if !(digits.len() == 6 || digits.len() == 8) || !digits.is_ascii() {
+            return Err(format!("Invalid hex color: {value}"));
+        }
+        let mut channels = [255u8; 4];
+        for (index, channel) in channels.iter_mut().enumerate().take(digits.len() / 2) {
+            *channel = u8::from_str_radix(&digits[index * 2..index * 2 + 2], 16)
+                .map_err(|_| format!("Invalid hex color: {value}"))?;
+        }
+        let [r, g, b, a] = channels.map(|channel| channel as f32 / 255.0);
+        Ok(Color::new(r, g, b, a))
+    }
+
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: f32) -> Color {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let saturation = saturation.clamp(0.0, 1.0);
+        let lightness = lightness.clamp(0.0, 1.0);
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = lightness - chroma * 0.5;
+        Color::new(r + m, g + m, b + m, alpha)
+    }
+
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let chroma = max - min;
+        let lightness = (max + min) * 0.5;
+        if chroma <= f32::EPSILON {
+            return (0.0, 0.0, lightness);
+        }
+        let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == self.r {
+            ((self.g - self.b) / chroma).rem_euclid(6.0)
+        } else if max == self.g {
+            (self.b - self.r) / chroma + 2.0
+        } else {
+            (self.r - self.g) / chroma + 4.0
+        };
+        (hue * 60.0, saturation, lightness)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite() && self.a.is_finite()
+    }
+
+    pub fn to_hex(self) -> String {
+        let [r, g, b, a] = [self.r, self.g, self.b, self.a]
+            .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+        if a == 255 {
+            format!("#{r:02x}{g:02x}{b:02x}")
+        } else {
+            format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+        }
+    }
 }
 
 impl Default for Color {
@@ -34,11 +104,31 @@ impl Default for Color {
     }
 }
 
+// Fields added since version 1 load through their serde defaults; bump this only when a saved
+// value has to be rewritten on load.
+pub const DOCUMENT_VERSION: u32 = 1;
+
+const CANVAS_CLAMP_MARGIN: f32 = 8.0;
+
+fn default_version() -> u32 {
+    1
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Document {
+    #[serde(default = "default_version")]
+    pub version: u32,
     pub canvas: Canvas,
     pub layers: Vec<Layer>,
     pub active_layer_id: u32,
+    #[serde(default)]
+    pub selection: Vec<u32>,
+    #[serde(default)]
+    pub guides: Vec<Guide>,
+    #[serde(default)]
+    pub palette: Vec<Color>,
+    #[serde(default)]
+    pub symbols: BTreeMap<u32, Element>,
     next_id: u32,
 }
 
@@ -46,25 +136,303 @@ impl Document {
     pub fn new(width: u32, height: u32) -> Self {
         let base_layer = Layer::new(1, "Layer 1");
         Self {
+            version: DOCUMENT_VERSION,
             canvas: Canvas::new(width, height),
             layers: vec![base_layer],
             active_layer_id: 1,
+            selection: Vec::new(),
+            guides: Vec::new(),
+            palette: Vec::new(),
+            symbols: BTreeMap::new(),
             next_id: 2,
         }
     }
 
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version > DOCUMENT_VERSION {
+            return Err(format!(
+                "Document version {} is newer than supported version {}",
+                self.version, DOCUMENT_VERSION
+            ));
+        }
+        if self.layers.is_empty() {
+            return Err("Document has no layers".to_string());
+        }
+        if !self.layers.iter().any(|layer| layer.id == self.active_layer_id) {
+            return Err(format!(
+                "Active layer {} does not exist",
+                self.active_layer_id
+            ));
+        }
+
+        let mut ids = HashSet::new();
+        for layer in &self.layers {
+            if !ids.insert(layer.id) {
+                return Err(format!("Duplicate id {}", layer.id));
+            }
+            for element in &layer.elements {
+                if !ids.insert(element.id) {
+                    return Err(format!("Duplicate id {}", element.id));
+                }
+                if !element.transform.is_finite() {
+                    return Err(format!(
+                        "Element {} has a non-finite transform",
+                        element.id
+                    ));
+                }
+                if !(0.0..=1.0).contains(&element.opacity) {
+                    return Err(format!("Element {} has an invalid opacity", element.id));
+                }
+            }
+        }
+        for (&symbol_id, master) in &self.symbols {
+            if !ids.insert(symbol_id) {
+                return Err(format!("Duplicate id {symbol_id}"));
+            }
+            if matches!(master.data, ElementData::Instance(_)) {
+                return Err(format!("Symbol {symbol_id} is itself an instance"));
+            }
+        }
+        let element_ids: HashSet<u32> = self
+            .layers
+            .iter()
+            .flat_map(|layer| &layer.elements)
+            .map(|element| element.id)
+            .collect();
+        let mut owners = BTreeMap::new();
+        for element in self.layers.iter().flat_map(|layer| &layer.elements) {
+            let ElementData::Group(group) = &element.data else {
+                continue;
+            };
+            if group
+                .layout
+                .is_some_and(|layout| !layout.spacing.is_finite() || !layout.padding.is_finite())
+            {
+                return Err(format!("Group {} has a non-finite layout", element.id));
+            }
+            for &child_id in &group.children {
+                if !element_ids.contains(&child_id) {
+                    return Err(format!(
+                        "Group {} references missing element {child_id}",
+                        element.id
+                    ));
+                }
+                if owners.insert(child_id, element.id).is_some() {
+                    return Err(format!("Element {child_id} belongs to more than one group"));
+                }
+            }
+        }
+        for &child_id in owners.keys() {
+            let mut current = child_id;
+            for _ in 0..=owners.len() {
+                match owners.get(&current) {
+                    Some(&owner) if owner == child_id => {
+                        return Err(format!("Group {child_id} contains itself"));
+                    }
+                    Some(&owner) => current = owner,
+                    None => break,
+                }
+            }
+        }
+        for element in self.layers.iter().flat_map(|layer| &layer.elements) {
+            if let ElementData::Instance(instance) = &element.data
+                && !self.symbols.contains_key(&instance.symbol_id)
+            {
+                return Err(format!(
+                    "Element {} references missing symbol {}",
+                    element.id, instance.symbol_id
+                ));
+            }
+        }
+        if self.guides.iter().any(|guide| !guide.position.is_finite()) {
+            return Err("Guide has a non-finite position".to_string());
+        }
+        Ok(())
+    }
+
     pub fn set_canvas_size(&mut self, width: u32, height: u32) {
+        if self.canvas.width > 0 && self.canvas.height > 0 {
+            let dx = width as f32 - self.canvas.width as f32;
+            let dy = height as f32 - self.canvas.height as f32;
+            for element in self.layers.iter_mut().flat_map(|layer| &mut layer.elements) {
+                let (fx, fy) = element.anchor.factors();
+                element.transform.x += dx * fx;
+                element.transform.y += dy * fy;
+            }
+        }
         self.canvas.width = width;
         self.canvas.height = height;
     }
 
+    pub fn add_guide(&mut self, guide: Guide) -> usize {
+        self.guides.push(guide);
+        self.guides.len() - 1
+    }
+
+    pub fn remove_guide(&mut self, index: usize) -> Option<Guide> {
+        if index < self.guides.len() {
+            Some(self.guides.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear_guides(&mut self) -> bool {
+        let had_guides = !self.guides.is_empty();
+        self.guides.clear();
+        had_guides
+    }
+
+    pub fn define_symbol(&mut self, element_id: u32) -> Option<u32> {
+        let element = self.get_element_by_id(element_id)?;
+        let mut master = self
+            .resolve_instance(element)
+            .unwrap_or_else(|| element.clone());
+        if matches!(master.data, ElementData::Instance(_)) {
+            return None;
+        }
+        let symbol_id = self.next_element_id();
+        master.id = symbol_id;
+        self.symbols.insert(symbol_id, master);
+        Some(symbol_id)
+    }
+
+    pub fn insert_symbol(&mut self, master: Element) -> bool {
+        if self.symbols.contains_key(&master.id) {
+            return false;
+        }
+        self.symbols.insert(master.id, master);
+        true
+    }
+
+    pub fn update_symbol(&mut self, symbol_id: u32, update: &ElementUpdate) -> bool {
+        let Some(master) = self.symbols.get_mut(&symbol_id) else {
+            return false;
+        };
+        let mut updated = master.clone();
+        update.apply_to(&mut updated);
+        if !updated.transform.is_finite() {
+            return false;
+        }
+        *master = updated;
+        true
+    }
+
+    pub fn element_path_data(&self, element_id: u32) -> Option<String> {
+        let element = self.get_element_by_id(element_id)?;
+        let resolved = self.resolve_instance(element);
+        Some(outline::outline_path(resolved.as_ref().unwrap_or(element)))
+    }
+
+    pub fn resolve_instance(&self, element: &Element) -> Option<Element> {
+        let ElementData::Instance(instance) = &element.data else {
+            return None;
+        };
+        let master = self.symbols.get(&instance.symbol_id)?;
+        let mut resolved = Element {
+            data: master.data.clone(),
+            ..element.clone()
+        };
+        instance.overrides.apply_to(&mut resolved);
+        Some(resolved)
+    }
+
+    pub fn add_palette_color(&mut self, color: Color) -> usize {
+        self.palette.push(color);
+        self.palette.len() - 1
+    }
+
+    pub fn remove_palette_color(&mut self, index: usize) -> Option<Color> {
+        if index < self.palette.len() {
+            Some(self.palette.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn snap_transform(
+        &self,
+        element_id: u32,
+        transform: Transform2D,
+        to_guides: bool,
+        to_elements: bool,
+        to_canvas: bool,
+        threshold: f32,
+    ) -> (Transform2D, Vec<Guide>) {
+        let mut x_targets = Vec::new();
+        let mut y_targets = Vec::new();
+        if to_guides {
+            for guide in &self.guides {
+                match guide.orientation {
+                    GuideOrientation::Vertical => x_targets.push(guide.position),
+                    GuideOrientation::Horizontal => y_targets.push(guide.position),
+                }
+            }
+        }
+        if to_elements {
+            let others = self
+                .layers
+                .iter()
+                .filter(|layer| layer.visible)
+                .flat_map(|layer| &layer.elements)
+                .filter(|element| element.visible && element.id != element_id);
+            for element in others {
+                let bounds = element.transform.bounds();
+                let center_x = bounds.x + bounds.width * 0.5;
+                let center_y = bounds.y + bounds.height * 0.5;
+                x_targets.extend([bounds.x, center_x, bounds.x + bounds.width]);
+                y_targets.extend([bounds.y, center_y, bounds.y + bounds.height]);
+            }
+        }
+        if to_canvas {
+            let width = self.canvas.width as f32;
+            let height = self.canvas.height as f32;
+            x_targets.extend([0.0, width * 0.5, width]);
+            y_targets.extend([0.0, height * 0.5, height]);
+        }
+        guide::snap_transform(transform, &x_targets, &y_targets, threshold)
+    }
+
+    pub fn clamp_to_canvas(&self, transform: Transform2D) -> Transform2D {
+        if self.canvas.width == 0 || self.canvas.height == 0 {
+            return transform;
+        }
+        let canvas_width = self.canvas.width as f32;
+        let canvas_height = self.canvas.height as f32;
+        let bounds = transform.bounds();
+        let keep_x = bounds.width.min(CANVAS_CLAMP_MARGIN);
+        let keep_y = bounds.height.min(CANVAS_CLAMP_MARGIN);
+        let clamped_x = bounds
+            .x
+            .min(canvas_width - keep_x)
+            .max(keep_x - bounds.width);
+        let clamped_y = bounds
+            .y
+            .min(canvas_height - keep_y)
+            .max(keep_y - bounds.height);
+        Transform2D {
+            x: transform.x + (clamped_x - bounds.x),
+            y: transform.y + (clamped_y - bounds.y),
+            ..transform
+        }
+    }
+
     pub fn next_element_id(&mut self) -> u32 {
         let id = self.next_id;
         self.next_id += 1;
         id
     }
 
-    pub fn recalculate_next_id(&mut self) {
+    pub fn next_id(&self) -> u32 {
+        self.next_id
+    }
+
+    pub fn set_next_id(&mut self, id: u32) -> u32 {
+        self.next_id = id.max(self.max_id().saturating_add(1));
+        self.next_id
+    }
+
+    fn max_id(&self) -> u32 {
         let mut max_id = 0;
         for layer in &self.layers {
             max_id = max_id.max(layer.id);
@@ -72,7 +440,14 @@ impl Document {
                 max_id = max_id.max(element.id);
             }
         }
-        self.next_id = max_id.saturating_add(1);
+        if let Some(&symbol_id) = self.symbols.keys().next_back() {
+            max_id = max_id.max(symbol_id);
+        }
+        max_id
+    }
+
+    pub fn recalculate_next_id(&mut self) {
+        self.next_id = self.max_id().saturating_add(1);
         if self.layers.is_empty() {
             let id = self.next_element_id();
             self.layers.push(Layer::new(id, "Layer 1"));
@@ -86,6 +461,44 @@ impl Document {
         id
     }
 
+    // Returns the layer's old index and the prior state of the groups that lost children to it.
+    pub fn remove_layer(&mut self, layer_id: u32) -> Option<(usize, Layer, Vec<Element>)> {
+        if self.layers.len() <= 1 {
+            return None;
+        }
+        let index = self.layers.iter().position(|layer| layer.id == layer_id)?;
+        let layer = self.layers.remove(index);
+        if self.active_layer_id == layer_id {
+            let adjacent = index.min(self.layers.len() - 1);
+            self.active_layer_id = self.layers[adjacent].id;
+        }
+        let mut touched: Vec<Element> = Vec::new();
+        for element in &layer.elements {
+            let children = match &element.data {
+                ElementData::Group(group) => group.children.clone(),
+                _ => Vec::new(),
+            };
+            for before in self.detach_from_group(element.id, children) {
+                if !touched.iter().any(|seen| seen.id == before.id) {
+                    touched.push(before);
+                }
+            }
+        }
+        let removed: HashSet<u32> = layer.elements.iter().map(|element| element.id).collect();
+        self.selection.retain(|id| !removed.contains(id));
+        Some((index, layer, touched))
+    }
+
+    pub fn restore_layer(&mut self, index: usize, layer: Layer, active_layer_id: u32) -> bool {
+        if self.layers.iter().any(|existing| existing.id == layer.id) {
+            return false;
+        }
+        let insert_index = index.min(self.layers.len());
+        self.layers.insert(insert_index, layer);
+        self.active_layer_id = active_layer_id;
+        true
+    }
+
     pub fn insert_element_at(&mut self, layer_id: u32, index: usize, element: Element) -> bool {
         if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == layer_id) {
             let insert_index = index.min(layer.elements.len());
@@ -114,6 +527,24 @@ impl Document {
         None
     }
 
+    pub fn move_element(
+        &mut self,
+        element_id: u32,
+        layer_id: u32,
+        index: Option<usize>,
+    ) -> Option<(u32, usize, usize)> {
+        if !self.layers.iter().any(|layer| layer.id == layer_id) {
+            return None;
+        }
+        let (from_layer_id, from_index, element) = self.remove_element_by_id(element_id)?;
+        let layer = self.layers.iter_mut().find(|layer| layer.id == layer_id)?;
+        let to_index = index
+            .unwrap_or(layer.elements.len())
+            .min(layer.elements.len());
+        layer.elements.insert(to_index, element);
+        Some((from_layer_id, from_index, to_index))
+    }
+
     pub fn replace_element_by_id(&mut self, element_id: u32, element: Element) -> bool {
         for layer in &mut self.layers {
             if let Some(index) = layer.elements.iter().position(|el| el.id == element_id) {
@@ -130,37 +561,315 @@ impl Document {
         index: usize,
         element: Element,
     ) -> bool {
-        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == layer_id) {
-            if index < layer.elements.len() && layer.elements[index].id == element.id {
-                layer.elements[index] = element;
-                return true;
-            }
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == layer_id)
+            && index < layer.elements.len()
+            && layer.elements[index].id == element.id
+        {
+            layer.elements[index] = element;
+            return true;
         }
         self.replace_element_by_id(element.id, element)
     }
 
-    pub fn apply_update(
-        &mut self,
-        element_id: u32,
-        update: &ElementUpdate,
-    ) -> Option<(u32, usize, Element, Element)> {
-        for layer in &mut self.layers {
-            if let Some(index) = layer.elements.iter().position(|el| el.id == element_id) {
-                let before = layer.elements[index].clone();
-                let mut after = before.clone();
-                update.apply_to(&mut after);
-                layer.elements[index] = after.clone();
-                return Some((layer.id, index, before, after));
-            }
+    pub fn apply_update(&mut self, element_id: u32, update: &ElementUpdate) -> bool {
+        let Some(element) = self.get_element_by_id_mut(element_id) else {
+            return false;
+        };
+        let mut after = element.clone();
+        update.apply_to(&mut after);
+        if !after.transform.is_finite() {
+            return false;
         }
-        None
+        let previous = std::mem::replace(element, after).transform;
+        let next = element.transform;
+        self.follow_group_move(element_id, &previous, &next);
+        self.reflow_group(element_id);
+        self.refit_owners(element_id);
+        true
     }
 
     pub fn set_element_transform(&mut self, element_id: u32, transform: Transform2D) -> bool {
-        for layer in &mut self.layers {
-            if let Some(element) = layer.elements.iter_mut().find(|el| el.id == element_id) {
-                element.transform = transform;
-                return true;
+        if !transform.is_finite() {
+            return false;
+        }
+        let Some(element) = self.get_element_by_id_mut(element_id) else {
+            return false;
+        };
+        let previous = std::mem::replace(&mut element.transform, transform);
+        self.follow_group_move(element_id, &previous, &transform);
+        self.reflow_group(element_id);
+        self.refit_owners(element_id);
+        true
+    }
+
+    // Children keep their place inside the group's box as it moves, resizes or rotates.
+    fn follow_group_move(&mut self, element_id: u32, previous: &Transform2D, next: &Transform2D) {
+        if previous == next {
+            return;
+        }
+        for child_id in self.descendants(element_id) {
+            if let Some(child) = self.get_element_by_id_mut(child_id) {
+                child.transform = child.transform.followed(previous, next);
+            }
+        }
+    }
+
+    pub fn descendants(&self, element_id: u32) -> Vec<u32> {
+        let mut descendants = Vec::new();
+        let mut pending = self.group_children(element_id);
+        while let Some(child_id) = pending.pop() {
+            if child_id == element_id || descendants.contains(&child_id) {
+                continue;
+            }
+            descendants.push(child_id);
+            pending.extend(self.group_children(child_id));
+        }
+        descendants
+    }
+
+    // Everything a transform change can touch: the element, its subtree and the groups owning it.
+    pub fn related_elements(&self, element_id: u32) -> Vec<Element> {
+        if self.get_element_by_id(element_id).is_none() {
+            return Vec::new();
+        }
+        let mut ids = vec![element_id];
+        ids.extend(self.descendants(element_id));
+        ids.extend(self.ancestors(element_id));
+        ids.into_iter()
+            .filter_map(|id| self.get_element_by_id(id).cloned())
+            .collect()
+    }
+
+    // Fits a group's box around its children, measured in the group's own rotated frame.
+    fn refit_group(&mut self, group_id: u32) -> bool {
+        if self.group_layout(group_id).is_some() {
+            return self.reflow_group(group_id);
+        }
+        let Some(frame) = self.get_element_transform(group_id) else {
+            return false;
+        };
+        let mut min = (f32::INFINITY, f32::INFINITY);
+        let mut max = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for child_id in self.group_children(group_id) {
+            let Some(transform) = self.get_element_transform(child_id) else {
+                continue;
+            };
+            for (x, y) in transform.corners() {
+                let (lx, ly) = frame.local_point(x, y);
+                min = (min.0.min(lx), min.1.min(ly));
+                max = (max.0.max(lx), max.1.max(ly));
+            }
+        }
+        if !min.0.is_finite() {
+            return false;
+        }
+        let (cx, cy) = frame.world_point((min.0 + max.0) * 0.5, (min.1 + max.1) * 0.5);
+        let fitted = Transform2D {
+            width: (max.0 - min.0).max(1.0),
+            height: (max.1 - min.1).max(1.0),
+            ..frame
+        }
+        .centered_at(cx, cy);
+        match self.get_element_by_id_mut(group_id) {
+            Some(group) if group.transform != fitted => {
+                group.transform = fitted;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn group_layout(&self, group_id: u32) -> Option<GroupLayout> {
+        match self.get_element_by_id(group_id).map(|element| &element.data) {
+            Some(ElementData::Group(group)) => group.layout,
+            _ => None,
+        }
+    }
+
+    pub fn set_group_layout(&mut self, group_id: u32, layout: Option<GroupLayout>) -> bool {
+        let Some(Element {
+            data: ElementData::Group(group),
+            ..
+        }) = self.get_element_by_id_mut(group_id)
+        else {
+            return false;
+        };
+        group.layout = layout;
+        if !self.reflow_group(group_id) {
+            self.refit_group(group_id);
+        }
+        self.refit_owners(group_id);
+        true
+    }
+
+    // Auto-layout lines children up along the canvas axes from the group's top-left corner and
+    // sizes the group to fit them.
+    fn reflow_group(&mut self, group_id: u32) -> bool {
+        let (Some(layout), Some(frame)) = (
+            self.group_layout(group_id),
+            self.get_element_transform(group_id),
+        ) else {
+            return false;
+        };
+        let mut cursor = layout.padding;
+        let mut cross: f32 = 0.0;
+        for child_id in self.group_children(group_id) {
+            let Some(bounds) = self.get_element_transform(child_id).map(|t| t.bounds()) else {
+                continue;
+            };
+            let (x, y, along, across) = match layout.direction {
+                LayoutDirection::Horizontal => {
+                    (cursor, layout.padding, bounds.width, bounds.height)
+                }
+                LayoutDirection::Vertical => (layout.padding, cursor, bounds.height, bounds.width),
+            };
+            self.translate_tree(child_id, frame.x + x - bounds.x, frame.y + y - bounds.y);
+            cursor += along + layout.spacing;
+            cross = cross.max(across);
+        }
+        let along = (cursor - layout.spacing).max(layout.padding) + layout.padding;
+        let across = cross + layout.padding * 2.0;
+        let (width, height) = match layout.direction {
+            LayoutDirection::Horizontal => (along, across),
+            LayoutDirection::Vertical => (across, along),
+        };
+        let Some(group) = self.get_element_by_id_mut(group_id) else {
+            return false;
+        };
+        group.transform.width = width.max(1.0);
+        group.transform.height = height.max(1.0);
+        true
+    }
+
+    fn translate_tree(&mut self, element_id: u32, dx: f32, dy: f32) {
+        for id in std::iter::once(element_id).chain(self.descendants(element_id)) {
+            if let Some(element) = self.get_element_by_id_mut(id) {
+                element.transform.x += dx;
+                element.transform.y += dy;
+            }
+        }
+    }
+
+    fn refit_owners(&mut self, element_id: u32) {
+        for group_id in self.ancestors(element_id) {
+            self.refit_group(group_id);
+        }
+    }
+
+    // Swaps a removed element for `adopted` in its owning group and returns the groups it
+    // touched as they were before, owner first.
+    pub fn detach_from_group(&mut self, element_id: u32, adopted: Vec<u32>) -> Vec<Element> {
+        let Some(group_id) = self.owning_group(element_id) else {
+            return Vec::new();
+        };
+        let touched: Vec<Element> = std::iter::once(group_id)
+            .chain(self.ancestors(group_id))
+            .filter_map(|id| self.get_element_by_id(id).cloned())
+            .collect();
+        let adopted: Vec<u32> = adopted
+            .into_iter()
+            .filter(|&id| self.get_element_by_id(id).is_some())
+            .collect();
+        if let Some(Element {
+            data: ElementData::Group(group),
+            ..
+        }) = self.get_element_by_id_mut(group_id)
+            && let Some(position) = group.children.iter().position(|&id| id == element_id)
+        {
+            group.children.splice(position..=position, adopted);
+        }
+        self.refit_group(group_id);
+        self.refit_owners(group_id);
+        touched
+    }
+
+    pub fn restore_elements(&mut self, elements: &[Element]) {
+        for element in elements {
+            self.replace_element_by_id(element.id, element.clone());
+        }
+    }
+
+    // Copies an element under a fresh id; groups copy their whole subtree, children first.
+    pub fn duplicate_tree(&mut self, element_id: u32) -> Vec<Element> {
+        let mut copies = Vec::new();
+        self.duplicate_into(element_id, &mut copies);
+        copies
+    }
+
+    fn duplicate_into(&mut self, element_id: u32, copies: &mut Vec<Element>) -> Option<u32> {
+        let mut element = self.get_element_by_id(element_id)?.clone();
+        if let ElementData::Group(group) = &mut element.data {
+            let children = std::mem::take(&mut group.children);
+            for child_id in children {
+                if let Some(copy_id) = self.duplicate_into(child_id, copies) {
+                    group.children.push(copy_id);
+                }
+            }
+        }
+        element.id = self.next_element_id();
+        let copy_id = element.id;
+        copies.push(element);
+        Some(copy_id)
+    }
+
+    pub fn group_children(&self, element_id: u32) -> Vec<u32> {
+        match self.get_element_by_id(element_id).map(|element| &element.data) {
+            Some(ElementData::Group(group)) => group.children.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Owning groups from the innermost outwards.
+    pub fn ancestors(&self, element_id: u32) -> Vec<u32> {
+        let mut ancestors = Vec::new();
+        let mut current = element_id;
+        while let Some(group_id) = self.owning_group(current) {
+            if group_id == element_id || ancestors.contains(&group_id) {
+                break;
+            }
+            ancestors.push(group_id);
+            current = group_id;
+        }
+        ancestors
+    }
+
+    pub fn owning_group(&self, element_id: u32) -> Option<u32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| &layer.elements)
+            .find(|element| match &element.data {
+                ElementData::Group(group) => group.children.contains(&element_id),
+                _ => false,
+            })
+            .map(|element| element.id)
+    }
+
+    // Clicking a grouped child selects its outermost group, stopping inside an entered group.
+    pub fn selectable_ancestor(&self, element_id: u32, entered_group: Option<u32>) -> u32 {
+        let mut current = element_id;
+        while let Some(group_id) = self.owning_group(current) {
+            if Some(group_id) == entered_group {
+                break;
+            }
+            current = group_id;
+        }
+        current
+    }
+
+    pub fn element_contains_point(&self, element_id: u32, x: f32, y: f32) -> bool {
+        let mut pending = vec![element_id];
+        let mut visited = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            match self.get_element_by_id(id) {
+                Some(Element {
+                    data: ElementData::Group(group),
+                    ..
+                }) => pending.extend(&group.children),
+                Some(element) if element.contains_point(x, y) => return true,
+                _ => {}
             }
         }
         false
@@ -193,6 +902,16 @@ impl Document {
         None
     }
 
+    pub fn find_by_name(&self, query: &str) -> Vec<u32> {
+        let query = query.to_lowercase();
+        self.layers
+            .iter()
+            .flat_map(|layer| &layer.elements)
+            .filter(|element| element.name.to_lowercase().contains(&query))
+            .map(|element| element.id)
+            .collect()
+    }
+
     pub fn find_element_location(&self, element_id: u32) -> Option<(u32, usize)> {
         for layer in &self.layers {
             if let Some(index) = layer.elements.iter().position(|el| el.id == element_id) {
@@ -202,23 +921,172 @@ impl Document {
         None
     }
 
+    pub fn content_bounds(&self) -> Option<Bounds> {
+        self.layers
+            .iter()
+            .filter(|layer| layer.visible)
+            .flat_map(|layer| &layer.elements)
+            .filter(|element| element.visible)
+            .map(|element| element.transform.bounds())
+            .reduce(|acc, bounds| acc.union(&bounds))
+    }
+
+    pub fn find_overlaps(&self) -> Vec<(u32, u32)> {
+        let mut boxes: Vec<(u32, Bounds)> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.visible)
+            .flat_map(|layer| &layer.elements)
+            // A group's box always covers its own children, so only leaf elements are compared.
+            .filter(|element| {
+                element.visible && !matches!(element.data, ElementData::Group(_))
+            })
+            .map(|element| (element.id, element.transform.bounds()))
+            .collect();
+        boxes.sort_by(|(_, a), (_, b)| a.x.total_cmp(&b.x));
+
+        // Sweep along x; only boxes that start before the current one ends can overlap.
+        let mut pairs = Vec::new();
+        for (index, (id, bounds)) in boxes.iter().enumerate() {
+            let right = bounds.x + bounds.width;
+            for (other_id, other) in boxes[index + 1..].iter() {
+                if other.x >= right {
+                    break;
+                }
+                if other.y < bounds.y + bounds.height && bounds.y < other.y + other.height {
+                    pairs.push((*id.min(other_id), *id.max(other_id)));
+                }
+            }
+        }
+        pairs
+    }
+
+    pub fn set_layer_opacity(&mut self, layer_id: u32, opacity: f32) -> bool {
+        match self.layers.iter_mut().find(|layer| layer.id == layer_id) {
+            Some(layer) => {
+                layer.opacity = opacity.clamp(0.0, 1.0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_layer_blend_mode(&mut self, layer_id: u32, blend_mode: BlendMode) -> bool {
+        match self.layers.iter_mut().find(|layer| layer.id == layer_id) {
+            Some(layer) => {
+                layer.blend_mode = blend_mode;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_layer_locked(&self, layer_id: u32) -> bool {
+        self.layers
+            .iter()
+            .find(|layer| layer.id == layer_id)
+            .is_some_and(|layer| layer.locked)
+    }
+
+    pub fn is_element_locked(&self, element_id: u32) -> bool {
+        self.layers.iter().any(|layer| {
+            layer
+                .elements
+                .iter()
+                .any(|el| el.id == element_id && (layer.locked || el.locked))
+        })
+    }
+
+    pub fn modify_element(
+        &mut self,
+        element_id: u32,
+        modify: impl FnOnce(&mut Element) -> bool,
+    ) -> Option<(u32, usize, Element, Element)> {
+        let (layer_id, index) = self.find_element_location(element_id)?;
+        let element = self.get_element_by_id_mut(element_id)?;
+        let before = element.clone();
+        if !modify(element) {
+            return None;
+        }
+        Some((layer_id, index, before, element.clone()))
+    }
+
+    pub fn set_element_locked(
+        &mut self,
+        element_id: u32,
+        locked: bool,
+    ) -> Option<(u32, usize, Element, Element)> {
+        self.modify_element(element_id, |element| {
+            std::mem::replace(&mut element.locked, locked) != locked
+        })
+    }
+
+    pub fn set_element_anchor(
+        &mut self,
+        element_id: u32,
+        anchor: Anchor,
+    ) -> Option<(u32, usize, Element, Element)> {
+        self.modify_element(element_id, |element| {
+            std::mem::replace(&mut element.anchor, anchor) != anchor
+        })
+    }
+
+    pub fn set_element_visible(
+        &mut self,
+        element_id: u32,
+        visible: bool,
+    ) -> Option<(u32, usize, Element, Element)> {
+        self.modify_element(element_id, |element| {
+            std::mem::replace(&mut element.visible, visible) != visible
+        })
+    }
+
     pub fn hit_test(&self, x: f32, y: f32) -> Option<u32> {
+        self.hit_test_all(x, y).into_iter().next()
+    }
+
+    pub fn hit_test_all(&self, x: f32, y: f32) -> Vec<u32> {
+        let mut hits = Vec::new();
         for layer in self.layers.iter().rev() {
             if !layer.visible || layer.locked {
                 continue;
             }
             for element in layer.elements.iter().rev() {
-                let transform = element.transform;
-                if x >= transform.x
-                    && x <= transform.x + transform.width
-                    && y >= transform.y
-                    && y <= transform.y + transform.height
-                {
-                    return Some(element.id);
+                if element.visible && !element.locked && element.contains_point(x, y) {
+                    hits.push(element.id);
                 }
             }
         }
-        None
+        hits
+    }
+
+    // Front-most first, matching hit_test_all.
+    pub fn hit_test_rect(&self, rect: Bounds) -> Vec<u32> {
+        let mut hits = Vec::new();
+        for layer in self.layers.iter().rev() {
+            if !layer.visible || layer.locked {
+                continue;
+            }
+            for element in layer.elements.iter().rev() {
+                // Groups are reached through their children, never through their own box.
+                if matches!(element.data, ElementData::Group(_)) {
+                    continue;
+                }
+                let bounds = element.transform.bounds();
+                if element.visible && !element.locked && bounds.intersects(&rect) {
+                    hits.push(element.id);
+                }
+            }
+        }
+        hits
+    }
+
+    pub fn restored_selection(&self) -> Vec<u32> {
+        self.selection
+            .iter()
+            .copied()
+            .filter(|&element_id| self.get_element_by_id(element_id).is_some())
+            .collect()
     }
 
     pub fn find_first_shape(&self) -> Option<u32> {
@@ -252,3 +1120,215 @@ impl Document {
         element_id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elements::GroupElement;
+
+    fn grouped_document() -> (Document, u32, [u32; 2]) {
+        let mut document = Document::new(800, 600);
+        let mut children = [0; 2];
+        for (child, x) in children.iter_mut().zip([0.0, 100.0]) {
+            *child = document.next_element_id();
+            let transform = Transform2D::new(x, 0.0, 50.0, 50.0);
+            let shape = Element::shape(*child, "Shape", ShapeElement::rectangle(), transform);
+            document.push_element(1, shape);
+        }
+        let group_id = document.next_element_id();
+        let group = Element::new(
+            group_id,
+            "Group",
+            Transform2D::new(0.0, 0.0, 150.0, 50.0),
+            ElementData::Group(GroupElement::new(children.to_vec())),
+        );
+        document.push_element(1, group);
+        (document, group_id, children)
+    }
+
+    #[test]
+    fn moving_a_child_refits_its_group() {
+        let (mut document, group_id, [_, child]) = grouped_document();
+        document.set_element_transform(child, Transform2D::new(200.0, 20.0, 50.0, 50.0));
+        let bounds = document.get_element_transform(group_id).unwrap().bounds();
+        assert_eq!((bounds.x, bounds.y, bounds.width, bounds.height), (0.0, 0.0, 250.0, 70.0));
+    }
+
+    #[test]
+    fn resizing_a_group_scales_its_children() {
+        let (mut document, group_id, [_, child]) = grouped_document();
+        document.set_element_transform(group_id, Transform2D::new(0.0, 0.0, 300.0, 100.0));
+        let transform = document.get_element_transform(child).unwrap();
+        assert!((transform.x - 200.0).abs() < 1e-3);
+        assert!((transform.y - 0.0).abs() < 1e-3);
+        assert!((transform.width - 100.0).abs() < 1e-3);
+        assert!((transform.height - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rotating_a_group_turns_its_children_about_its_pivot() {
+        let (mut document, group_id, [first, _]) = grouped_document();
+        let mut transform = document.get_element_transform(group_id).unwrap();
+        transform.rotation = std::f32::consts::PI;
+        document.set_element_transform(group_id, transform);
+        let child = document.get_element_transform(first).unwrap();
+        let (cx, cy) = child.center();
+        assert!((cx - 125.0).abs() < 1e-3 && (cy - 25.0).abs() < 1e-3);
+        assert!((child.rotation - std::f32::consts::PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn layout_stacks_children_and_fits_the_group() {
+        let (mut document, group_id, [first_id, second_id]) = grouped_document();
+        let layout = GroupLayout {
+            direction: LayoutDirection::Vertical,
+            spacing: 10.0,
+            padding: 5.0,
+        };
+        assert!(document.set_group_layout(group_id, Some(layout)));
+        let first = document.get_element_transform(first_id).unwrap();
+        let second = document.get_element_transform(second_id).unwrap();
+        assert_eq!((first.x, first.y), (5.0, 5.0));
+        assert_eq!((second.x, second.y), (5.0, 65.0));
+        let group = document.get_element_transform(group_id).unwrap();
+        assert_eq!((group.width, group.height), (60.0, 120.0));
+
+        document.set_element_transform(first_id, Transform2D::new(300.0, 300.0, 50.0, 80.0));
+        let second = document.get_element_transform(second_id).unwrap();
+        assert_eq!((second.x, second.y), (5.0, 95.0));
+        let group = document.get_element_transform(group_id).unwrap();
+        assert_eq!(group.height, 150.0);
+    }
+
+    #[test]
+    fn detaching_a_deleted_child_keeps_the_document_valid() {
+        let (mut document, group_id, [first, second]) = grouped_document();
+        document.remove_element_by_id(second);
+        let touched = document.detach_from_group(second, Vec::new());
+        assert_eq!(touched.first().map(|element| element.id), Some(group_id));
+        assert_eq!(document.group_children(group_id), vec![first]);
+        assert_eq!(document.get_element_transform(group_id).unwrap().width, 50.0);
+        assert!(document.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_newer_versions() {
+        let (mut document, _, _) = grouped_document();
+        assert!(document.validate().is_ok());
+        document.version = DOCUMENT_VERSION + 1;
+        assert!(document.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_missing_children() {
+        let (mut document, _, [_, second]) = grouped_document();
+        document.remove_element_by_id(second);
+        assert!(document.validate().is_err());
+    }
+
+    #[test]
+    fn rect_hits_skip_group_boxes() {
+        let (document, group_id, _) = grouped_document();
+        let hits = document.hit_test_rect(Bounds {
+            x: 60.0,
+            y: 10.0,
+            width: 20.0,
+            height: 20.0,
+        });
+        assert!(!hits.contains(&group_id));
+    }
+
+    #[test]
+    fn stroked_lines_hit_and_bound_their_full_width() {
+        let shape = ShapeElement {
+            shape_type: crate::elements::ShapeType::Line,
+            stroke: Some(crate::elements::Stroke {
+                color: Color::new(0.0, 0.0, 0.0, 1.0),
+                width: 10.0,
+            }),
+            ..ShapeElement::rectangle()
+        };
+        let line = Element::shape(1, "Line", shape, Transform2D::new(0.0, 100.0, 200.0, 0.0));
+        assert!(line.contains_point(100.0, 104.5));
+        assert!(line.contains_point(203.0, 100.0));
+        assert!(!line.contains_point(100.0, 106.0));
+        let bounds = line.visual_bounds();
+        assert_eq!((bounds.y, bounds.height), (95.0, 10.0));
+    }
+
+    #[test]
+    fn overlaps_skip_groups() {
+        let (mut document, _, children) = grouped_document();
+        assert!(document.find_overlaps().is_empty());
+        let id = document.next_element_id();
+        let transform = Transform2D::new(25.0, 25.0, 50.0, 50.0);
+        document.push_element(1, Element::shape(id, "Shape", ShapeElement::rectangle(), transform));
+        assert_eq!(document.find_overlaps(), vec![(children[0], id)]);
+    }
+
+    #[test]
+    fn instance_fill_overrides_replace_each_other() {
+        let mut instance = Element::new(
+            1,
+            "Instance",
+            Transform2D::new(0.0, 0.0, 50.0, 50.0),
+            ElementData::Instance(crate::elements::InstanceElement::new(2)),
+        );
+        let fill = ElementUpdate {
+            fill: Some(Color::new(1.0, 0.0, 0.0, 1.0)),
+            ..ElementUpdate::default()
+        };
+        fill.apply_to(&mut instance);
+        let hex = ElementUpdate {
+            fill_hex: Some("#0000ff".to_string()),
+            ..ElementUpdate::default()
+        };
+        hex.apply_to(&mut instance);
+        let ElementData::Instance(data) = &instance.data else {
+            unreachable!();
+        };
+        assert_eq!(data.overrides.fill_color().map(Color::to_hex).as_deref(), Some("#0000ff"));
+        fill.apply_to(&mut instance);
+        let ElementData::Instance(data) = &instance.data else {
+            unreachable!();
+        };
+        assert_eq!(data.overrides.fill_hex, None);
+    }
+
+    #[test]
+    fn undoing_an_added_symbol_removes_it() {
+        let mut document = Document::new(800, 600);
+        let mut history = History::new();
+        let id = document.next_element_id();
+        let master = Element::shape(
+            id,
+            "Symbol",
+            ShapeElement::rectangle(),
+            Transform2D::new(0.0, 0.0, 50.0, 50.0),
+        );
+        assert!(document.insert_symbol(master.clone()));
+        history.record(Command::AddSymbol { master });
+        assert!(history.undo(&mut document));
+        assert!(!document.symbols.contains_key(&id));
+        assert!(history.redo(&mut document));
+        assert!(document.symbols.contains_key(&id));
+    }
+
+    #[test]
+    fn flipping_a_rotated_element_keeps_its_center() {
+        let mut transform = Transform2D::new(40.0, 60.0, 120.0, 80.0);
+        transform.rotation = 0.6;
+        transform.pivot = (0.0, 0.0);
+        let original = transform;
+        let (cx, cy) = transform.center();
+        for flip in [Transform2D::flip_horizontal, Transform2D::flip_vertical] {
+            flip(&mut transform);
+            let (x, y) = transform.center();
+            assert!((x - cx).abs() < 1e-3 && (y - cy).abs() < 1e-3, "{x},{y} != {cx},{cy}");
+            flip(&mut transform);
+            assert!((transform.x - original.x).abs() < 1e-3);
+            assert!((transform.y - original.y).abs() < 1e-3);
+            assert_eq!(transform.pivot, original.pivot);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::f32::consts::TAU;
+use std::fmt::Write;
+
+use crate::elements::{ElementData, ShapeType};
+
+use super::{Element, Transform2D};
+
+struct PathWriter<'a> {
+    transform: &'a Transform2D,
+    data: String,
+}
+
+impl PathWriter<'_> {
+    fn point(&mut self, command: char, lx: f32, ly: f32) {
+        let (x, y) = self.transform.world_point(lx, ly);
+        let _ = write!(self.data, "{command} {} {} ", round(x), round(y));
+    }
+
+    fn arc(&mut self, rx: f32, ry: f32, lx: f32, ly: f32) {
+        let (x, y) = self.transform.world_point(lx, ly);
+        let rotation = round(self.transform.rotation.to_degrees());
+        let _ = write!(
+            self.data,
+            "A {} {} {rotation} 0 1 {} {} ",
+            round(rx),
+            round(ry),
+            round(x),
+            round(y)
+        );
+    }
+
+    fn close(mut self) -> String {
+        self.data.push('Z');
+        self.data
+    }
+}
+
+fn round(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+// Mirrors the silhouettes the fragment shader draws, in document coordinates.
+pub fn outline_path(element: &Element) -> String {
+    let transform = &element.transform;
+    let half_w = transform.width * 0.5;
+    let half_h = transform.height * 0.5;
+    let mut path = PathWriter {
+        transform,
+        data: String::new(),
+    };
+    let shape = match &element.data {
+        ElementData::Shape(shape) => Some(shape),
+        _ => None,
+    };
+    match shape.map(|shape| (shape.shape_type, shape.sides, shape.corner_radius)) {
+        Some((ShapeType::Line, _, _)) => {
+            let [(ax, ay), (bx, by)] = transform.line_endpoints();
+            return format!("M {} {} L {} {}", round(ax), round(ay), round(bx), round(by));
+        }
+        Some((ShapeType::Ellipse, _, _)) => {
+            path.point('M', half_w, 0.0);
+            path.arc(half_w, half_h, -half_w, 0.0);
+            path.arc(half_w, half_h, half_w, 0.0);
+        }
+        Some((ShapeType::Polygon, sides, _)) => {
+            let sides = sides.max(3);
+            let sector = TAU / sides as f32;
+            // Only odd polygons are asymmetric vertically, so only a vertical flip shows.
+            let flip_y = if transform.scale_y < 0.0 { -1.0 } else { 1.0 };
+            for index in 0..sides {
+                let (sin, cos) = (sector * index as f32).sin_cos();
+                let command = if index == 0 { 'M' } else { 'L' };
+                path.point(command, sin * half_w, -cos * half_h * flip_y);
+            }
+        }
+        Some((ShapeType::Rect, _, corner_radius)) if corner_radius > 0.0 => {
+            let radius = corner_radius.min(half_w).min(half_h);
+            path.point('M', -half_w + radius, -half_h);
+            path.point('L', half_w - radius, -half_h);
+            path.arc(radius, radius, half_w, -half_h + radius);
+            path.point('L', half_w, half_h - radius);
+            path.arc(radius, radius, half_w - radius, half_h);
+            path.point('L', -half_w + radius, half_h);
+            path.arc(radius, radius, -half_w, half_h - radius);
+            path.point('L', -half_w, -half_h + radius);
+            path.arc(radius, radius, -half_w + radius, -half_h);
+        }
+        _ => {
+            for (index, (lx, ly)) in transform.local_corners().into_iter().enumerate() {
+                path.point(if index == 0 { 'M' } else { 'L' }, lx, ly);
+            }
+        }
+    }
+    path.close()
+}
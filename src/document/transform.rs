@@ -7,6 +7,58 @@ pub struct Transform2D {
     pub width: f32,
     pub height: f32,
     pub rotation: f32,
+    #[serde(default = "default_scale")]
+    pub scale_x: f32,
+    #[serde(default = "default_scale")]
+    pub scale_y: f32,
+    #[serde(default = "default_pivot")]
+    pub pivot: (f32, f32),
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_pivot() -> (f32, f32) {
+    (0.5, 0.5)
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Measurement {
+    pub dx: f32,
+    pub dy: f32,
+    pub distance: f32,
+    pub angle: f32,
+}
+
+impl Bounds {
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        let min_x = self.x.min(other.x);
+        let min_y = self.y.min(other.y);
+        let max_x = (self.x + self.width).max(other.x + other.width);
+        let max_y = (self.y + self.height).max(other.y + other.height);
+        Bounds {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        }
+    }
 }
 
 impl Transform2D {
@@ -17,6 +69,184 @@ impl Transform2D {
             width,
             height,
             rotation: 0.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            pivot: default_pivot(),
         }
     }
+
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite()
+            && self.y.is_finite()
+            && self.width.is_finite()
+            && self.height.is_finite()
+            && self.rotation.is_finite()
+            && self.scale_x.is_finite()
+            && self.scale_y.is_finite()
+            && self.pivot.0.is_finite()
+            && self.pivot.1.is_finite()
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        self.world_point(0.0, 0.0)
+    }
+
+    pub fn pivot_point(&self) -> (f32, f32) {
+        (
+            self.x + self.width * self.pivot.0,
+            self.y + self.height * self.pivot.1,
+        )
+    }
+
+    // Offset from the pivot to the unrotated center.
+    fn pivot_offset(&self) -> (f32, f32) {
+        (
+            (0.5 - self.pivot.0) * self.width,
+            (0.5 - self.pivot.1) * self.height,
+        )
+    }
+
+    pub fn local_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let (px, py) = self.pivot_point();
+        let (ox, oy) = self.pivot_offset();
+        let (sin, cos) = self.rotation.sin_cos();
+        let dx = x - px;
+        let dy = y - py;
+        (dx * cos + dy * sin - ox, -dx * sin + dy * cos - oy)
+    }
+
+    pub fn contains_point(&self, x: f32, y: f32) -> bool {
+        let (lx, ly) = self.local_point(x, y);
+        lx.abs() <= self.width * 0.5 && ly.abs() <= self.height * 0.5
+    }
+
+    pub fn world_point(&self, lx: f32, ly: f32) -> (f32, f32) {
+        let (px, py) = self.pivot_point();
+        let (ox, oy) = self.pivot_offset();
+        let (sin, cos) = self.rotation.sin_cos();
+        let (lx, ly) = (lx + ox, ly + oy);
+        (px + lx * cos - ly * sin, py + lx * sin + ly * cos)
+    }
+
+    // Lines run from the top-left to the bottom-right corner, mirrored by flips.
+    pub fn line_endpoints(&self) -> [(f32, f32); 2] {
+        let half_w = self.width * 0.5 * self.scale_x.signum();
+        let half_h = self.height * 0.5 * self.scale_y.signum();
+        [
+            self.world_point(-half_w, -half_h),
+            self.world_point(half_w, half_h),
+        ]
+    }
+
+    pub fn local_corners(&self) -> [(f32, f32); 4] {
+        let half_w = self.width * 0.5;
+        let half_h = self.height * 0.5;
+        [(-half_w, -half_h), (half_w, -half_h), (half_w, half_h), (-half_w, half_h)]
+    }
+
+    pub fn corners(&self) -> [(f32, f32); 4] {
+        self.local_corners().map(|(lx, ly)| self.world_point(lx, ly))
+    }
+
+    pub fn resized_from_corner(&self, corner: usize, x: f32, y: f32) -> Transform2D {
+        let corners = self.local_corners();
+        let (anchor_x, anchor_y) = corners[(corner + 2) % 4];
+        let (lx, ly) = self.local_point(x, y);
+        let width = (lx - anchor_x).abs().max(1.0);
+        let height = (ly - anchor_y).abs().max(1.0);
+        self.resized_from_anchor(corner, width, height)
+    }
+
+    pub fn resized_from_anchor(&self, corner: usize, width: f32, height: f32) -> Transform2D {
+        let corners = self.local_corners();
+        let (anchor_x, anchor_y) = corners[(corner + 2) % 4];
+        let (corner_x, corner_y) = corners[corner % 4];
+        let center_x = anchor_x + corner_x.signum() * width * 0.5;
+        let center_y = anchor_y + corner_y.signum() * height * 0.5;
+        let (cx, cy) = self.world_point(center_x, center_y);
+        Transform2D {
+            width,
+            height,
+            ..*self
+        }
+        .centered_at(cx, cy)
+    }
+
+    pub fn centered_at(&self, cx: f32, cy: f32) -> Transform2D {
+        let (current_x, current_y) = self.center();
+        Transform2D {
+            x: self.x + cx - current_x,
+            y: self.y + cy - current_y,
+            ..*self
+        }
+    }
+
+    // Maps a transform held inside a group's box as that box goes from `previous` to `next`.
+    pub fn followed(&self, previous: &Transform2D, next: &Transform2D) -> Transform2D {
+        let scale_x = next.width / previous.width.max(f32::EPSILON);
+        let scale_y = next.height / previous.height.max(f32::EPSILON);
+        let (cx, cy) = self.center();
+        let (lx, ly) = previous.local_point(cx, cy);
+        let (cx, cy) = next.world_point(lx * scale_x, ly * scale_y);
+        // Axes turned against the group stretch by a mix of both factors.
+        let (sin, cos) = (self.rotation - previous.rotation).sin_cos();
+        Transform2D {
+            width: self.width * (cos * scale_x).hypot(sin * scale_y),
+            height: self.height * (sin * scale_x).hypot(cos * scale_y),
+            rotation: self.rotation + next.rotation - previous.rotation,
+            ..*self
+        }
+        .centered_at(cx, cy)
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        let corners = self.corners();
+        let mut min_x = f32::INFINITY;
+        let mut min_y = f32::INFINITY;
+        let mut max_x = f32::NEG_INFINITY;
+        let mut max_y = f32::NEG_INFINITY;
+        for (x, y) in corners {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        Bounds {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        }
+    }
+
+    pub fn measure_to(&self, other: &Transform2D) -> Measurement {
+        let (ax, ay) = self.center();
+        let (bx, by) = other.center();
+        let dx = bx - ax;
+        let dy = by - ay;
+        Measurement {
+            dx,
+            dy,
+            distance: dx.hypot(dy),
+            angle: dy.atan2(dx),
+        }
+    }
+
+    // Flips mirror about the element's own center, so a mirrored off-center pivot stays on the
+    // same spot relative to it and the element doesn't move.
+    pub fn flip_horizontal(&mut self) {
+        let (cx, cy) = self.center();
+        self.scale_x = -self.scale_x;
+        self.rotation = -self.rotation;
+        self.pivot.0 = 1.0 - self.pivot.0;
+        *self = self.centered_at(cx, cy);
+    }
+
+    pub fn flip_vertical(&mut self) {
+        let (cx, cy) = self.center();
+        self.scale_y = -self.scale_y;
+        self.rotation = -self.rotation;
+        self.pivot.1 = 1.0 - self.pivot.1;
+        *self = self.centered_at(cx, cy);
+    }
 }
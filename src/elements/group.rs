@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum LayoutDirection {
+    Horizontal,
+    Vertical,
+}
+
+impl LayoutDirection {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "horizontal" => Some(LayoutDirection::Horizontal),
+            "vertical" => Some(LayoutDirection::Vertical),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct GroupLayout {
+    pub direction: LayoutDirection,
+    pub spacing: f32,
+    pub padding: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupElement {
+    pub children: Vec<u32>,
+    #[serde(default)]
+    pub layout: Option<GroupLayout>,
+}
+
+impl GroupElement {
+    pub fn new(children: Vec<u32>) -> Self {
+        Self {
+            children,
+            layout: None,
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::document::Bounds;
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ImageFilters {
     pub brightness: f32,
@@ -23,6 +25,8 @@ pub struct ImageElement {
     pub source: String,
     #[serde(default)]
     pub filters: ImageFilters,
+    #[serde(default)]
+    pub crop: Option<Bounds>,
 }
 
 impl ImageElement {
@@ -30,6 +34,7 @@ impl ImageElement {
         Self {
             source: source.into(),
             filters: ImageFilters::default(),
+            crop: None,
         }
     }
 }
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+use crate::document::ElementUpdate;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstanceElement {
+    pub symbol_id: u32,
+    #[serde(default)]
+    pub overrides: Box<ElementUpdate>,
+}
+
+impl InstanceElement {
+    pub fn new(symbol_id: u32) -> Self {
+        Self {
+            symbol_id,
+            overrides: Box::default(),
+        }
+    }
+}
@@ -1,17 +1,22 @@
+pub mod group;
 pub mod image;
+pub mod instance;
 pub mod shape;
 pub mod text;
 
 use serde::{Deserialize, Serialize};
 
+pub use group::{GroupElement, GroupLayout, LayoutDirection};
 pub use image::ImageElement;
-#[allow(unused_imports)]
+pub use instance::InstanceElement;
 pub use shape::{Fill, ShapeElement, ShapeType, Stroke};
-pub use text::TextElement;
+pub use text::{TextAlign, TextElement};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ElementData {
     Shape(ShapeElement),
     Text(TextElement),
     Image(ImageElement),
+    Instance(InstanceElement),
+    Group(GroupElement),
 }
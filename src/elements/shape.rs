@@ -10,6 +10,18 @@ pub enum ShapeType {
     Polygon,
 }
 
+impl ShapeType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rect" | "rectangle" => Some(ShapeType::Rect),
+            "ellipse" => Some(ShapeType::Ellipse),
+            "line" => Some(ShapeType::Line),
+            "polygon" => Some(ShapeType::Polygon),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Fill {
     pub color: Color,
@@ -21,11 +33,19 @@ pub struct Stroke {
     pub width: f32,
 }
 
+fn default_sides() -> u32 {
+    4
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ShapeElement {
     pub shape_type: ShapeType,
     pub fill: Option<Fill>,
     pub stroke: Option<Stroke>,
+    #[serde(default = "default_sides")]
+    pub sides: u32,
+    #[serde(default)]
+    pub corner_radius: f32,
 }
 
 impl ShapeElement {
@@ -36,6 +56,8 @@ impl ShapeElement {
                 color: Color::new(0.86, 0.42, 0.25, 1.0),
             }),
             stroke: None,
+            sides: default_sides(),
+            corner_radius: 0.0,
         }
     }
 }
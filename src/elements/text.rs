@@ -2,12 +2,45 @@ use serde::{Deserialize, Serialize};
 
 use crate::document::Color;
 
+use super::Stroke;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(TextAlign::Left),
+            "center" => Some(TextAlign::Center),
+            "right" => Some(TextAlign::Right),
+            _ => None,
+        }
+    }
+}
+
+fn default_line_height() -> f32 {
+    1.2
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TextElement {
     pub content: String,
     pub font_family: String,
     pub font_size: f32,
     pub fill: Color,
+    #[serde(default)]
+    pub align: TextAlign,
+    #[serde(default = "default_line_height")]
+    pub line_height: f32,
+    #[serde(default)]
+    pub letter_spacing: f32,
+    #[serde(default)]
+    pub stroke: Option<Stroke>,
 }
 
 impl TextElement {
@@ -17,6 +50,10 @@ impl TextElement {
             font_family: "system-ui".to_string(),
             font_size: 24.0,
             fill: Color::new(0.1, 0.1, 0.1, 1.0),
+            align: TextAlign::Left,
+            line_height: default_line_height(),
+            letter_spacing: 0.0,
+            stroke: None,
         }
     }
 }
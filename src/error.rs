@@ -0,0 +1,98 @@
+use serde::Serialize;
+use wasm_bindgen::JsValue;
+
+#[derive(Clone, Debug)]
+pub enum EngineError {
+    Renderer(String),
+    InvalidInput(String),
+    Serialization(String),
+    InvalidDocument(String),
+    InvalidColor(String),
+    InvalidValue(&'static str),
+    LayerNotFound,
+    ElementNotFound,
+    LayerLocked,
+    ReadOnly,
+    SymbolNotFound,
+    UnknownShapeType,
+    UnknownTextAlign,
+    UnknownTool,
+    UnknownFlipAxis,
+    UnknownGuideOrientation,
+    UnknownFitMode,
+    UnknownBlendMode,
+    UnknownAnchor,
+    UnknownQuality,
+    UnknownLayoutDirection,
+}
+
+#[derive(Serialize)]
+struct ErrorPayload<'a> {
+    code: &'static str,
+    message: &'a str,
+}
+
+impl EngineError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::Renderer(_) => "renderer",
+            EngineError::InvalidInput(_) => "invalid_input",
+            EngineError::Serialization(_) => "serialization",
+            EngineError::InvalidDocument(_) => "invalid_document",
+            EngineError::InvalidColor(_) => "invalid_color",
+            EngineError::InvalidValue(_) => "invalid_value",
+            EngineError::LayerNotFound => "layer_not_found",
+            EngineError::ElementNotFound => "element_not_found",
+            EngineError::LayerLocked => "layer_locked",
+            EngineError::ReadOnly => "read_only",
+            EngineError::SymbolNotFound => "symbol_not_found",
+            EngineError::UnknownShapeType => "unknown_shape_type",
+            EngineError::UnknownTextAlign => "unknown_text_align",
+            EngineError::UnknownTool => "unknown_tool",
+            EngineError::UnknownFlipAxis => "unknown_flip_axis",
+            EngineError::UnknownGuideOrientation => "unknown_guide_orientation",
+            EngineError::UnknownFitMode => "unknown_fit_mode",
+            EngineError::UnknownBlendMode => "unknown_blend_mode",
+            EngineError::UnknownAnchor => "unknown_anchor",
+            EngineError::UnknownQuality => "unknown_quality",
+            EngineError::UnknownLayoutDirection => "unknown_layout_direction",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            EngineError::Renderer(message)
+            | EngineError::InvalidInput(message)
+            | EngineError::Serialization(message)
+            | EngineError::InvalidColor(message) => message.clone(),
+            EngineError::InvalidDocument(message) => format!("Invalid document: {message}"),
+            EngineError::InvalidValue(message) => message.to_string(),
+            EngineError::LayerNotFound => "Layer not found".to_string(),
+            EngineError::ElementNotFound => "Element not found".to_string(),
+            EngineError::LayerLocked => "Layer is locked".to_string(),
+            EngineError::ReadOnly => "Document is read-only".to_string(),
+            EngineError::SymbolNotFound => "Symbol not found".to_string(),
+            EngineError::UnknownShapeType => "Unknown shape type".to_string(),
+            EngineError::UnknownTextAlign => "Unknown text alignment".to_string(),
+            EngineError::UnknownTool => "Unknown tool".to_string(),
+            EngineError::UnknownFlipAxis => "Unknown flip axis".to_string(),
+            EngineError::UnknownGuideOrientation => "Unknown guide orientation".to_string(),
+            EngineError::UnknownFitMode => "Unknown fit mode".to_string(),
+            EngineError::UnknownBlendMode => "Unknown blend mode".to_string(),
+            EngineError::UnknownAnchor => "Unknown anchor".to_string(),
+            EngineError::UnknownQuality => "Unknown quality tier".to_string(),
+            EngineError::UnknownLayoutDirection => "Unknown layout direction".to_string(),
+        }
+    }
+}
+
+impl From<EngineError> for JsValue {
+    fn from(err: EngineError) -> JsValue {
+        let message = err.message();
+        let payload = ErrorPayload {
+            code: err.code(),
+            message: &message,
+        };
+        serde_wasm_bindgen::to_value(&payload).unwrap_or_else(|_| JsValue::from_str(&message))
+    }
+}
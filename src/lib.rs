@@ -1,12 +1,32 @@
 mod document;
 mod elements;
+mod error;
+mod png;
 mod renderer;
+mod viewport;
 
-use document::{Command, Document, Element, ElementUpdate, History, Transform2D};
-use elements::{ElementData, ImageElement, ShapeElement, ShapeType, TextElement};
-use renderer::{Rect, RenderShape, Renderer, ShapeKind};
+use document::{
+    Anchor, BlendMode, Bounds, Color, Command, Document, Element, ElementUpdate, Fit, Guide,
+    GuideOrientation, History, JournalEntry, Layer, LayerSummary, Transform2D,
+};
+use error::EngineError;
+use elements::{
+    ElementData, GroupElement, GroupLayout, ImageElement, InstanceElement, LayoutDirection,
+    ShapeElement, ShapeType, TextAlign, TextElement,
+};
+use renderer::{
+    Backdrop, Overlay, Quality, Rect, RenderImage, RenderShadow, RenderShape, RenderStroke,
+    RenderText, Renderer, Selection, SelectionStyle, ShapeKind,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use viewport::Viewport;
 use wasm_bindgen::prelude::*;
 
+const ROTATION_SNAP_DEGREES: f32 = 15.0;
+const DEFAULT_SNAP_THRESHOLD: f32 = 6.0;
+const FRAME_INTERVAL_MS: f64 = 1000.0 / 60.0;
+
 #[derive(Clone, Copy, Debug)]
 enum Tool {
     Select,
@@ -19,6 +39,42 @@ enum Tool {
 struct TransformSnapshot {
     element_id: u32,
     before: Element,
+    related: Vec<Element>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PointerHit {
+    Handle { index: u8 },
+    Element { id: u32 },
+    Empty,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ElementDescriptor {
+    #[serde(default)]
+    name: Option<String>,
+    transform: Transform2D,
+    data: ElementData,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CreateSession {
+    element_id: u32,
+    origin_x: f32,
+    origin_y: f32,
+}
+
+#[wasm_bindgen]
+pub fn rgba_to_hsl(r: f32, g: f32, b: f32, a: f32) -> Vec<f32> {
+    let (hue, saturation, lightness) = Color::new(r, g, b, a).to_hsl();
+    vec![hue, saturation, lightness, a]
+}
+
+#[wasm_bindgen]
+pub fn hsl_to_rgba(h: f32, s: f32, l: f32, a: f32) -> Vec<f32> {
+    let color = Color::from_hsl(h, s, l, a);
+    vec![color.r, color.g, color.b, color.a]
 }
 
 #[wasm_bindgen]
@@ -26,37 +82,130 @@ pub struct PigmoraEngine {
     renderer: Renderer,
     document: Document,
     history: History,
-    selected_element_id: Option<u32>,
+    selected_ids: Vec<u32>,
     active_tool: Tool,
     active_shape_type: ShapeType,
     transform_snapshot: Option<TransformSnapshot>,
+    aspect_lock: bool,
+    clamp_to_canvas: bool,
+    dirty: bool,
+    render_shapes: Vec<RenderShape>,
+    marquee: Option<Rect>,
+    selection_style: SelectionStyle,
+    rotation_snap: bool,
+    create_session: Option<CreateSession>,
+    constrain: bool,
+    guide_rects: Vec<Rect>,
+    snap_to_guides: bool,
+    smart_snap: bool,
+    snap_to_canvas: bool,
+    snap_threshold: f32,
+    snap_lines: Vec<Guide>,
+    last_insert_index: Option<usize>,
+    read_only: bool,
+    deterministic: bool,
+    render_on_resize: bool,
+    default_fill: Color,
+    render_throttle: bool,
+    last_render_at: Option<f64>,
+    dropped_renders: u32,
+    viewport: Viewport,
+    entered_group: Option<u32>,
 }
 
+// Pointer-driven methods (select_at, handle_at, pointer_down, the create and drag handlers, the
+// hit tests and zoom_at) take screen pixels and map them through the viewport. Everything that
+// places or edits an element directly, such as add_*, set_rect and nudge_selected, works in
+// document units.
 #[wasm_bindgen]
 impl PigmoraEngine {
     #[wasm_bindgen(constructor)]
-    pub fn new(canvas_id: &str) -> Result<PigmoraEngine, JsValue> {
+    pub fn new(canvas_id: &str) -> Result<PigmoraEngine, EngineError> {
         console_error_panic_hook::set_once();
-        let renderer = Renderer::new(canvas_id)?;
-        Ok(PigmoraEngine {
-            renderer,
-            document: Document::new(0, 0),
-            history: History::new(),
-            selected_element_id: None,
-            active_tool: Tool::Select,
-            active_shape_type: ShapeType::Rect,
-            transform_snapshot: None,
-        })
+        let renderer = Renderer::new(canvas_id)
+            .map_err(|err| EngineError::Renderer(err.as_string().unwrap_or_default()))?;
+        Ok(Self::with_renderer(renderer))
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         self.renderer.resize(width, height);
         self.document.set_canvas_size(width, height);
+        self.viewport.width = width as f32;
+        self.viewport.height = height as f32;
+        self.dirty = true;
+        if self.render_on_resize {
+            self.render();
+        }
+    }
+
+    pub fn pending_resize(&self) -> bool {
+        self.renderer.pending_resize()
+    }
+
+    pub fn set_render_on_resize(&mut self, enabled: bool) {
+        self.render_on_resize = enabled;
+    }
+
+    pub fn set_viewport(&mut self, zoom: f32, pan_x: f32, pan_y: f32) -> bool {
+        let changed = self.viewport.set(zoom, pan_x, pan_y);
+        self.dirty |= changed;
+        changed
+    }
+
+    pub fn get_viewport(&self) -> Result<JsValue, EngineError> {
+        serde_wasm_bindgen::to_value(&self.viewport)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn zoom_at(&mut self, screen_x: f32, screen_y: f32, factor: f32) -> bool {
+        let changed = self.viewport.zoom_at(screen_x, screen_y, factor);
+        self.dirty |= changed;
+        changed
+    }
+
+    pub fn scroll_into_view(&mut self, element_id: u32, margin: f32) -> bool {
+        if !margin.is_finite() {
+            return false;
+        }
+        let Some(transform) = self.document.get_element_transform(element_id) else {
+            return false;
+        };
+        let changed = self
+            .viewport
+            .scroll_into_view(&transform.bounds(), margin.max(0.0));
+        self.dirty |= changed;
+        changed
+    }
+
+    pub fn zoom_to_fit(&mut self, padding: f32) {
+        match self.document.content_bounds() {
+            Some(bounds) => self.viewport.fit(&bounds, padding.max(0.0)),
+            None => self.viewport.reset(),
+        }
+        self.dirty = true;
+    }
+
+    pub fn canvas_width(&self) -> u32 {
+        self.document.canvas.width
+    }
+
+    pub fn canvas_height(&self) -> u32 {
+        self.document.canvas.height
     }
 
     pub fn set_rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
-        let transform = Transform2D::new(x, y, width, height);
-        let element_id = match self.selected_element_id {
+        if self.read_only {
+            return;
+        }
+        let mut transform = Transform2D::new(x, y, width, height);
+        if !transform.is_finite() {
+            return;
+        }
+        if self.clamp_to_canvas {
+            transform = self.document.clamp_to_canvas(transform);
+        }
+        let element_id = match self.selected_element_id() {
+            Some(element_id) if self.document.is_element_locked(element_id) => return,
             Some(element_id) => {
                 self.document
                     .set_element_transform(element_id, transform);
@@ -64,358 +213,2460 @@ impl PigmoraEngine {
             }
             None => {
                 let element_id = self.document.ensure_primary_shape(transform);
-                self.selected_element_id = Some(element_id);
+                self.selected_ids = vec![element_id];
                 element_id
             }
         };
-        self.selected_element_id = Some(element_id);
+        self.selected_ids = vec![element_id];
+        self.dirty = true;
     }
 
-    pub fn render(&mut self) {
-        let (rects, selected) = self.collect_rects();
-        self.renderer.render(&rects, selected);
+    pub fn set_read_only(&mut self, read_only: bool) {
+        if read_only {
+            self.cancel_create();
+            self.commit_transform();
+        }
+        self.read_only = read_only;
     }
 
-    pub fn get_document(&self) -> Result<JsValue, JsValue> {
-        serde_wasm_bindgen::to_value(&self.document)
-            .map_err(|err| JsValue::from_str(&err.to_string()))
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn load_document(&mut self, value: JsValue) -> Result<(), JsValue> {
-        let document: Document = serde_wasm_bindgen::from_value(value)
-            .map_err(|err| JsValue::from_str(&err.to_string()))?;
-        self.document = document;
-        self.document.recalculate_next_id();
-        self.history.clear();
-        self.selected_element_id = self.document.find_first_shape();
-        self.sync_selection();
-        Ok(())
+    // Deterministic mode suffixes new element names with their id so names don't depend on
+    // creation order.
+    pub fn set_deterministic(&mut self, enabled: bool) {
+        self.deterministic = enabled;
+        self.dirty = true;
     }
 
-    pub fn undo(&mut self) -> bool {
-        let changed = self.history.undo(&mut self.document);
-        if changed {
-            self.sync_selection();
-        }
-        changed
+    pub fn set_render_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.renderer.set_on_image_load(callback);
     }
 
-    pub fn redo(&mut self) -> bool {
-        let changed = self.history.redo(&mut self.document);
-        if changed {
-            self.sync_selection();
-        }
-        changed
+    pub fn set_render_throttle(&mut self, enabled: bool) {
+        self.render_throttle = enabled;
+        self.last_render_at = None;
     }
 
-    pub fn add_shape(&mut self, shape_type: &str, x: f32, y: f32) -> Result<u32, JsValue> {
-        let shape_type = parse_shape_type(shape_type)?;
-        let transform = Transform2D::new(x, y, 160.0, 120.0);
-        let element_id = self.document.next_element_id();
-        let shape = ShapeElement {
-            shape_type,
-            ..ShapeElement::rectangle()
+    pub fn dropped_renders(&self) -> u32 {
+        self.dropped_renders
+    }
+
+    pub fn render(&mut self) {
+        if self.render_throttle
+            && let Some(now) = performance_now()
+        {
+            if self
+                .last_render_at
+                .is_some_and(|last| now - last < FRAME_INTERVAL_MS)
+            {
+                self.dropped_renders = self.dropped_renders.saturating_add(1);
+                return;
+            }
+            self.last_render_at = Some(now);
+        }
+        let selection = self.collect_rects();
+        self.collect_guides();
+        let overlay = Overlay {
+            selection,
+            selection_style: self.selection_style,
+            marquee: self.marquee,
+            guides: &self.guide_rects,
         };
-        let element = document::Element::shape(element_id, "Shape", shape, transform);
-        let layer_id = self.document.active_layer_id;
-        let index = self
-            .document
-            .push_element(layer_id, element.clone())
-            .ok_or_else(|| JsValue::from_str("Layer not found"))?;
-        self.history.record(Command::AddElement {
-            layer_id,
-            index,
-            element,
-        });
-        self.selected_element_id = Some(element_id);
-        self.sync_selection();
-        Ok(element_id)
+        let background = self.document.canvas.background;
+        let backdrop = Backdrop {
+            rect: Rect {
+                x: 0.0,
+                y: 0.0,
+                width: self.document.canvas.width as f32,
+                height: self.document.canvas.height as f32,
+            },
+            color: [background.r, background.g, background.b, background.a],
+            image: self
+                .document
+                .canvas
+                .background_image
+                .as_ref()
+                .map(|source| RenderImage {
+                    source: source.clone(),
+                    brightness: 1.0,
+                    contrast: 1.0,
+                    saturation: 1.0,
+                    crop: None,
+                }),
+            fit: self.document.canvas.background_fit,
+        };
+        self.renderer
+            .set_view(self.viewport.zoom, self.viewport.pan_x, self.viewport.pan_y);
+        self.renderer
+            .render(&backdrop, &self.render_shapes, &overlay);
+        self.dirty = false;
     }
 
-    pub fn add_text(&mut self, content: &str, x: f32, y: f32) -> Result<u32, JsValue> {
-        let transform = Transform2D::new(x, y, 240.0, 80.0);
-        let element_id = self.document.next_element_id();
-        let text = TextElement::new(content);
-        let element = document::Element::text(element_id, "Text", text, transform);
-        let layer_id = self.document.active_layer_id;
-        let index = self
-            .document
-            .push_element(layer_id, element.clone())
-            .ok_or_else(|| JsValue::from_str("Layer not found"))?;
-        self.history.record(Command::AddElement {
-            layer_id,
-            index,
-            element,
-        });
-        self.selected_element_id = Some(element_id);
-        self.sync_selection();
-        Ok(element_id)
+    pub fn export_layers_png(&mut self, include_hidden: bool) -> Result<JsValue, EngineError> {
+        let area = Bounds {
+            x: 0.0,
+            y: 0.0,
+            width: self.document.canvas.width as f32,
+            height: self.document.canvas.height as f32,
+        };
+        // The screen framebuffer has no alpha, so layers render offscreen to stay stackable.
+        let region = Rect {
+            x: area.x,
+            y: area.y,
+            width: area.width,
+            height: area.height,
+        };
+        let exports = js_sys::Map::new();
+        let mut shapes = Vec::new();
+        for layer in &self.document.layers {
+            if !layer.visible && !include_hidden {
+                continue;
+            }
+            shapes.clear();
+            push_layer_shapes(
+                &self.document,
+                layer,
+                &area,
+                None,
+                self.default_fill,
+                &mut shapes,
+            );
+            let (width, height, pixels) = self
+                .renderer
+                .capture_region(&region, 1.0, &shapes)
+                .ok_or_else(|| EngineError::Renderer("Failed to read pixels".to_string()))?;
+            let png = png::encode_rgba(width, height, &pixels);
+            exports.set(
+                &JsValue::from(layer.id),
+                &js_sys::Uint8Array::from(png.as_slice()),
+            );
+        }
+        self.dirty = true;
+        Ok(exports.into())
     }
 
-    pub fn add_image(
+    pub fn render_element_to_png(
         &mut self,
-        source: String,
-        width: f32,
-        height: f32,
-        x: f32,
-        y: f32,
-    ) -> Result<u32, JsValue> {
-        let transform = Transform2D::new(x, y, width.max(1.0), height.max(1.0));
-        let element_id = self.document.next_element_id();
-        let image = ImageElement::new(source);
-        let element = document::Element::image(element_id, "Image", image, transform);
-        let layer_id = self.document.active_layer_id;
-        let index = self
+        element_id: u32,
+        scale: f32,
+    ) -> Result<Vec<u8>, EngineError> {
+        if !scale.is_finite() || scale <= 0.0 {
+            return Err(EngineError::InvalidValue("Scale must be a positive number"));
+        }
+        let (layer_id, index) = self
             .document
-            .push_element(layer_id, element.clone())
-            .ok_or_else(|| JsValue::from_str("Layer not found"))?;
-        self.history.record(Command::AddElement {
-            layer_id,
-            index,
-            element,
-        });
-        self.selected_element_id = Some(element_id);
-        self.sync_selection();
-        Ok(element_id)
+            .find_element_location(element_id)
+            .ok_or(EngineError::ElementNotFound)?;
+        let layer = self
+            .document
+            .layers
+            .iter()
+            .find(|layer| layer.id == layer_id)
+            .ok_or(EngineError::LayerNotFound)?;
+        let element = &layer.elements[index];
+        let bounds = element.visual_bounds();
+        let region = Rect {
+            x: bounds.x,
+            y: bounds.y,
+            width: bounds.width,
+            height: bounds.height,
+        };
+        let mut shapes = Vec::new();
+        push_element_shape(&self.document, layer, element, self.default_fill, &mut shapes);
+        let (width, height, pixels) = self
+            .renderer
+            .capture_region(&region, scale, &shapes)
+            .ok_or_else(|| EngineError::Renderer("Failed to read pixels".to_string()))?;
+        self.dirty = true;
+        Ok(png::encode_rgba(width, height, &pixels))
     }
 
-    pub fn delete_element(&mut self, element_id: u32) -> bool {
-        if let Some((layer_id, index, element)) = self.document.remove_element_by_id(element_id) {
-            self.history.record(Command::DeleteElement {
-                layer_id,
-                index,
-                element,
-            });
-            if self.selected_element_id == Some(element_id) {
-                self.selected_element_id = self.document.find_first_shape();
-            }
-            self.sync_selection();
-            return true;
+    pub fn set_canvas_background_image(
+        &mut self,
+        source: Option<String>,
+        fit: &str,
+    ) -> Result<(), EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
         }
-        false
+        let fit = Fit::from_name(fit).ok_or(EngineError::UnknownFitMode)?;
+        self.document.canvas.background_image = source.filter(|source| !source.is_empty());
+        self.document.canvas.background_fit = fit;
+        self.dirty = true;
+        Ok(())
     }
 
-    pub fn update_element(&mut self, element_id: u32, props: JsValue) -> Result<bool, JsValue> {
-        let update: ElementUpdate = serde_wasm_bindgen::from_value(props)
-            .map_err(|err| JsValue::from_str(&err.to_string()))?;
-        if let Some((layer_id, index, before, after)) =
-            self.document.apply_update(element_id, &update)
-        {
-            self.history.record(Command::UpdateElement {
-                layer_id,
-                index,
-                before,
-                after,
-            });
-            if self.selected_element_id == Some(element_id) {
-                self.sync_selection();
-            }
-            return Ok(true);
+    pub fn set_layer_opacity(&mut self, layer_id: u32, opacity: f32) -> bool {
+        if self.read_only {
+            return false;
         }
-        Ok(false)
+        if !opacity.is_finite() {
+            return false;
+        }
+        let changed = self.document.set_layer_opacity(layer_id, opacity);
+        self.dirty |= changed;
+        changed
     }
 
-    pub fn apply_filter(&mut self, element_id: u32, filter: &str, value: f32) -> bool {
-        let location = self.document.find_element_location(element_id);
-        let (before, after) = {
-            let element = match self.document.get_element_by_id_mut(element_id) {
-                Some(element) => element,
-                None => return false,
-            };
-            let before = element.clone();
-            let updated = match &mut element.data {
-                ElementData::Image(image) => {
-                    match filter {
-                        "brightness" => image.filters.brightness = value.clamp(0.0, 2.0),
-                        "contrast" => image.filters.contrast = value.clamp(0.0, 2.0),
-                        "saturation" => image.filters.saturation = value.clamp(0.0, 2.0),
-                        _ => return false,
-                    }
-                    true
-                }
-                _ => false,
-            };
-            if !updated {
-                return false;
-            }
-            let after = element.clone();
-            (before, after)
-        };
-
-        if let Some((layer_id, index)) = location {
-            self.history.record(Command::UpdateElement {
-                layer_id,
-                index,
-                before,
-                after,
-            });
+    pub fn set_layer_blend_mode(&mut self, layer_id: u32, mode: &str) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
         }
-        true
+        let blend_mode = BlendMode::from_name(mode).ok_or(EngineError::UnknownBlendMode)?;
+        let changed = self.document.set_layer_blend_mode(layer_id, blend_mode);
+        self.dirty |= changed;
+        Ok(changed)
     }
 
-    pub fn get_selected_id(&self) -> Option<u32> {
-        self.selected_element_id
+    pub fn set_partial_render(&mut self, enabled: bool) {
+        self.renderer.set_partial_render(enabled);
+        self.dirty = true;
     }
 
-    pub fn set_active_tool(&mut self, tool: &str) -> Result<(), JsValue> {
-        self.active_tool = match tool {
-            "select" => Tool::Select,
-            "shape" => Tool::Shape,
-            "text" => Tool::Text,
-            "image" => Tool::Image,
-            _ => return Err(JsValue::from_str("Unknown tool")),
-        };
+    pub fn set_default_fill(&mut self, hex: &str) -> Result<(), EngineError> {
+        self.default_fill = Color::from_hex(hex).map_err(EngineError::InvalidColor)?;
+        self.dirty = true;
         Ok(())
     }
 
-    pub fn set_active_shape(&mut self, shape_type: &str) -> Result<(), JsValue> {
-        self.active_shape_type = parse_shape_type(shape_type)?;
+    pub fn set_transparency_grid(&mut self, enabled: bool) {
+        self.renderer.set_transparency_grid(enabled);
+        self.dirty = true;
+    }
+
+    pub fn set_quality(&mut self, tier: &str) -> Result<(), EngineError> {
+        let quality = Quality::from_name(tier).ok_or(EngineError::UnknownQuality)?;
+        self.renderer.set_quality(quality);
+        self.dirty = true;
         Ok(())
     }
 
-    pub fn select_at(&mut self, x: f32, y: f32) -> Option<u32> {
-        let hit = self.document.hit_test(x, y);
-        self.selected_element_id = hit;
-        hit
+    pub fn get_quality(&self) -> String {
+        self.renderer.quality().name().to_string()
     }
 
-    pub fn select_element(&mut self, element_id: u32) -> bool {
-        if self.document.get_element_by_id(element_id).is_some() {
-            self.selected_element_id = Some(element_id);
-            return true;
-        }
-        false
+    pub fn set_marquee(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        let (x0, y0) = self.viewport.screen_to_document(x, y);
+        let (x1, y1) = self.viewport.screen_to_document(x + width, y + height);
+        self.marquee = Some(Rect {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            width: (x1 - x0).abs(),
+            height: (y1 - y0).abs(),
+        });
+        self.dirty = true;
     }
 
-    pub fn begin_transform(&mut self) -> bool {
-        let element_id = match self.selected_element_id {
-            Some(element_id) => element_id,
-            None => return false,
-        };
-        let element = match self.document.get_element_by_id(element_id) {
-            Some(element) => element.clone(),
-            None => return false,
-        };
-        self.transform_snapshot = Some(TransformSnapshot { element_id, before: element });
-        true
+    pub fn clear_marquee(&mut self) {
+        if self.marquee.take().is_some() {
+            self.dirty = true;
+        }
     }
 
-    pub fn update_selected_transform(
+    pub fn set_selection_style(
         &mut self,
-        x: f32,
-        y: f32,
-        width: f32,
-        height: f32,
-    ) -> bool {
-        let element_id = match self.selected_element_id {
-            Some(element_id) => element_id,
-            None => return false,
+        handle_size: f32,
+        outline_color: &[f32],
+        handle_color: &[f32],
+    ) -> Result<(), EngineError> {
+        let outline_color: [f32; 4] = outline_color
+            .try_into()
+            .map_err(|_| EngineError::InvalidValue("Outline color must have 4 components"))?;
+        let handle_color: [f32; 4] = handle_color
+            .try_into()
+            .map_err(|_| EngineError::InvalidValue("Handle color must have 4 components"))?;
+        self.selection_style = SelectionStyle {
+            handle_size: handle_size.max(0.0),
+            outline_color,
+            handle_color,
         };
-        if let Some(element) = self.document.get_element_by_id_mut(element_id) {
-            element.transform.x = x;
-            element.transform.y = y;
-            element.transform.width = width.max(1.0);
-            element.transform.height = height.max(1.0);
-            return true;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn needs_render(&self) -> bool {
+        self.dirty || self.renderer.has_pending_images()
+    }
+
+    pub fn get_document(&self) -> Result<JsValue, EngineError> {
+        let mut document = self.document.clone();
+        document.selection = self.selected_ids.clone();
+        serde_wasm_bindgen::to_value(&document)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn get_element(&self, element_id: u32) -> Result<JsValue, EngineError> {
+        match self.document.get_element_by_id(element_id) {
+            Some(element) => serde_wasm_bindgen::to_value(element)
+                .map_err(|err| EngineError::Serialization(err.to_string())),
+            None => Ok(JsValue::NULL),
         }
-        false
     }
 
-    pub fn update_selected_text_size(&mut self, font_size: f32) -> bool {
-        let element_id = match self.selected_element_id {
-            Some(element_id) => element_id,
-            None => return false,
-        };
-        if let Some(element) = self.document.get_element_by_id_mut(element_id) {
-            if let ElementData::Text(text) = &mut element.data {
-                text.font_size = font_size.max(1.0);
-                return true;
-            }
+    pub fn get_selected_element(&self) -> Result<JsValue, EngineError> {
+        match self.selected_element_id() {
+            Some(element_id) => self.get_element(element_id),
+            None => Ok(JsValue::NULL),
         }
-        false
     }
 
-    pub fn commit_transform(&mut self) -> bool {
-        let snapshot = match self.transform_snapshot.take() {
-            Some(snapshot) => snapshot,
-            None => return false,
-        };
-        let after = match self.document.get_element_by_id(snapshot.element_id) {
-            Some(element) => element.clone(),
-            None => return false,
+    pub fn peek_next_id(&self) -> u32 {
+        self.document.next_id()
+    }
+
+    pub fn set_next_id(&mut self, id: u32) -> u32 {
+        if self.read_only {
+            return self.document.next_id();
+        }
+        self.document.set_next_id(id)
+    }
+
+    pub fn remove_layer(&mut self, layer_id: u32) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        if self.document.is_layer_locked(layer_id) {
+            return Err(EngineError::LayerLocked);
+        }
+        self.cancel_create();
+        self.commit_transform();
+        let active_layer_id = self.document.active_layer_id;
+        let Some((index, layer, touched)) = self.document.remove_layer(layer_id) else {
+            return Ok(false);
+        };
+        if let Some(group_id) = self.entered_group
+            && layer.elements.iter().any(|element| element.id == group_id)
+        {
+            self.entered_group = None;
+        }
+        // Groups elsewhere that lost children are recorded too, so undo restores them with the layer.
+        let mut commands = vec![Command::RemoveLayer {
+            index,
+            layer,
+            active_layer_id,
+        }];
+        commands.extend(self.update_commands(touched));
+        if let Some(command) = batch_command(commands) {
+            self.record(command);
+        }
+        self.sync_selection();
+        Ok(true)
+    }
+
+    pub fn get_layers_summary(&self) -> Result<JsValue, EngineError> {
+        let summary: Vec<LayerSummary> =
+            self.document.layers.iter().map(|layer| layer.summary()).collect();
+        serde_wasm_bindgen::to_value(&summary)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn load_document(&mut self, value: JsValue) -> Result<(), EngineError> {
+        let document: Document = serde_wasm_bindgen::from_value(value)
+            .map_err(|err| EngineError::InvalidInput(err.to_string()))?;
+        document
+            .validate()
+            .map_err(EngineError::InvalidDocument)?;
+        self.document = document;
+        self.document.recalculate_next_id();
+        self.history.clear();
+        self.create_session = None;
+        self.selected_ids = self.document.restored_selection();
+        if self.selected_ids.is_empty() {
+            self.selected_ids.extend(self.document.find_first_shape());
+        }
+        self.sync_selection();
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn merge_document(
+        &mut self,
+        value: JsValue,
+        offset_x: f32,
+        offset_y: f32,
+    ) -> Result<Vec<u32>, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let other: Document = serde_wasm_bindgen::from_value(value)
+            .map_err(|err| EngineError::InvalidInput(err.to_string()))?;
+        other
+            .validate()
+            .map_err(EngineError::InvalidDocument)?;
+        if !offset_x.is_finite() || !offset_y.is_finite() {
+            return Err(EngineError::InvalidValue("Offset must be finite"));
+        }
+
+        let layer_id = self.document.active_layer_id;
+        if !self.document.layers.iter().any(|layer| layer.id == layer_id) {
+            return Err(EngineError::LayerNotFound);
+        }
+        if self.document.is_layer_locked(layer_id) {
+            return Err(EngineError::LayerLocked);
+        }
+        // Imported symbols join the batch so undoing the merge drops them with their instances.
+        let mut symbol_map = BTreeMap::new();
+        let mut commands = Vec::new();
+        for (symbol_id, mut master) in other.symbols {
+            master.id = self.document.next_element_id();
+            symbol_map.insert(symbol_id, master.id);
+            self.document.insert_symbol(master.clone());
+            commands.push(Command::AddSymbol { master });
+        }
+        let elements: Vec<Element> = other
+            .layers
+            .into_iter()
+            .flat_map(|layer| layer.elements)
+            .collect();
+        let id_map: BTreeMap<u32, u32> = elements
+            .iter()
+            .map(|element| (element.id, self.document.next_element_id()))
+            .collect();
+        let mut element_ids = Vec::new();
+        for mut element in elements {
+            element.id = id_map[&element.id];
+            match &mut element.data {
+                ElementData::Group(group) => {
+                    for child_id in &mut group.children {
+                        *child_id = id_map[child_id];
+                    }
+                }
+                ElementData::Instance(instance) => {
+                    instance.symbol_id = symbol_map[&instance.symbol_id];
+                }
+                _ => {}
+            }
+            element.transform.x += offset_x;
+            element.transform.y += offset_y;
+            let index = self
+                .document
+                .push_element(layer_id, element.clone())
+                .ok_or(EngineError::LayerNotFound)?;
+            element_ids.push(element.id);
+            commands.push(Command::AddElement {
+                layer_id,
+                index,
+                element,
+            });
+        }
+        if !commands.is_empty() {
+            self.record(Command::Batch { commands });
+        }
+        Ok(element_ids)
+    }
+
+    pub fn array_duplicate(
+        &mut self,
+        element_id: u32,
+        rows: u32,
+        cols: u32,
+        dx: f32,
+        dy: f32,
+    ) -> Vec<u32> {
+        if self.read_only {
+            return Vec::new();
+        }
+        if !dx.is_finite() || !dy.is_finite() {
+            return Vec::new();
+        }
+        let (layer_id, mut index) = match self.document.find_element_location(element_id) {
+            Some(location) => location,
+            None => return Vec::new(),
+        };
+        if self.document.is_layer_locked(layer_id) {
+            return Vec::new();
+        }
+        if self.document.get_element_by_id(element_id).is_none() {
+            return Vec::new();
+        }
+        let mut element_ids = Vec::new();
+        let mut commands = Vec::new();
+        for row in 0..rows {
+            for col in 0..cols {
+                if row == 0 && col == 0 {
+                    continue;
+                }
+                // Groups are copied with their children so no two groups share a child.
+                for mut element in self.document.duplicate_tree(element_id) {
+                    element.transform.x += col as f32 * dx;
+                    element.transform.y += row as f32 * dy;
+                    index += 1;
+                    self.document
+                        .insert_element_at(layer_id, index, element.clone());
+                    commands.push(Command::AddElement {
+                        layer_id,
+                        index,
+                        element,
+                    });
+                }
+                if let Some(Command::AddElement { element, .. }) = commands.last() {
+                    element_ids.push(element.id);
+                }
+            }
+        }
+        if !commands.is_empty() {
+            self.record(Command::Batch { commands });
+        }
+        element_ids
+    }
+
+    pub fn add_elements(&mut self, value: JsValue) -> Result<Vec<u32>, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let descriptors: Vec<ElementDescriptor> = serde_wasm_bindgen::from_value(value)
+            .map_err(|err| EngineError::InvalidInput(err.to_string()))?;
+        let layer_id = self.document.active_layer_id;
+        if self.document.is_layer_locked(layer_id) {
+            return Err(EngineError::LayerLocked);
+        }
+        for descriptor in &descriptors {
+            if !descriptor.transform.is_finite() {
+                return Err(EngineError::InvalidValue("Transform must be finite"));
+            }
+            if let ElementData::Instance(instance) = &descriptor.data
+                && !self.document.symbols.contains_key(&instance.symbol_id)
+            {
+                return Err(EngineError::SymbolNotFound);
+            }
+            if matches!(descriptor.data, ElementData::Group(_)) {
+                return Err(EngineError::InvalidInput(
+                    "Groups must be created with group_selected".to_string(),
+                ));
+            }
+        }
+
+        let mut element_ids = Vec::with_capacity(descriptors.len());
+        let mut commands = Vec::with_capacity(descriptors.len());
+        for descriptor in descriptors {
+            let name = descriptor.name.unwrap_or_else(|| {
+                match descriptor.data {
+                    ElementData::Shape(_) => "Shape",
+                    ElementData::Text(_) => "Text",
+                    ElementData::Image(_) => "Image",
+                    ElementData::Instance(_) => "Instance",
+                    ElementData::Group(_) => "Group",
+                }
+                .to_string()
+            });
+            let element_id = self.document.next_element_id();
+            let mut element = Element::new(element_id, name, descriptor.transform, descriptor.data);
+            self.pin_name(&mut element);
+            let index = self
+                .document
+                .push_element(layer_id, element.clone())
+                .ok_or(EngineError::LayerNotFound)?;
+            element_ids.push(element_id);
+            commands.push(Command::AddElement {
+                layer_id,
+                index,
+                element,
+            });
+        }
+        if !commands.is_empty() {
+            self.record(Command::Batch { commands });
+        }
+        Ok(element_ids)
+    }
+
+    pub fn undo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let changed = self.history.undo(&mut self.document);
+        if changed {
+            self.sync_selection();
+            self.dirty = true;
+        }
+        changed
+    }
+
+    pub fn redo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let changed = self.history.redo(&mut self.document);
+        if changed {
+            self.sync_selection();
+            self.dirty = true;
+        }
+        changed
+    }
+
+    pub fn export_operations(&self) -> Result<JsValue, EngineError> {
+        serde_wasm_bindgen::to_value(self.history.journal())
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn apply_operations(&mut self, value: JsValue) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let entries: Vec<JournalEntry> = serde_wasm_bindgen::from_value(value)
+            .map_err(|err| EngineError::InvalidInput(err.to_string()))?;
+        let applied = self.history.replay(entries, &mut self.document);
+        self.document.recalculate_next_id();
+        self.sync_selection();
+        self.dirty = true;
+        Ok(applied as u32)
+    }
+
+    pub fn add_shape(&mut self, shape_type: &str, x: f32, y: f32) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let shape_type = parse_shape_type(shape_type)?;
+        self.create_shape(shape_type, x, y, true)
+    }
+
+    pub fn add_shape_quiet(
+        &mut self,
+        shape_type: &str,
+        x: f32,
+        y: f32,
+    ) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let shape_type = parse_shape_type(shape_type)?;
+        self.create_shape(shape_type, x, y, false)
+    }
+
+    pub fn add_text(&mut self, content: &str, x: f32, y: f32) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        self.create_text(content, x, y, true)
+    }
+
+    pub fn add_text_quiet(&mut self, content: &str, x: f32, y: f32) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        self.create_text(content, x, y, false)
+    }
+
+    pub fn define_symbol(&mut self, element_id: u32) -> Option<u32> {
+        if self.read_only {
+            return None;
+        }
+        self.document.define_symbol(element_id)
+    }
+
+    pub fn instantiate_symbol(
+        &mut self,
+        symbol_id: u32,
+        x: f32,
+        y: f32,
+    ) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let master = self
+            .document
+            .symbols
+            .get(&symbol_id)
+            .ok_or(EngineError::SymbolNotFound)?;
+        let name = master.name.clone();
+        let transform = Transform2D::new(x, y, master.transform.width, master.transform.height);
+        let element_id = self.document.next_element_id();
+        let data = ElementData::Instance(InstanceElement::new(symbol_id));
+        self.insert_new_element(Element::new(element_id, name, transform, data), true)
+    }
+
+    pub fn update_symbol(&mut self, symbol_id: u32, props: JsValue) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let update = parse_update(props)?;
+        if !self.document.symbols.contains_key(&symbol_id) {
+            return Err(EngineError::SymbolNotFound);
+        }
+        let updated = self.document.update_symbol(symbol_id, &update);
+        self.dirty |= updated;
+        Ok(updated)
+    }
+
+    pub fn add_image(
+        &mut self,
+        source: String,
+        width: f32,
+        height: f32,
+        x: f32,
+        y: f32,
+    ) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        self.create_image(source, width, height, x, y, true)
+    }
+
+    pub fn add_image_quiet(
+        &mut self,
+        source: String,
+        width: f32,
+        height: f32,
+        x: f32,
+        y: f32,
+    ) -> Result<u32, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        self.create_image(source, width, height, x, y, false)
+    }
+
+    pub fn pointer_down(&mut self, x: f32, y: f32) -> Option<u32> {
+        if let Tool::Select = self.active_tool {
+            return self.select_at(x, y);
+        }
+        if self.read_only {
+            return None;
+        }
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        match self.active_tool {
+            Tool::Select => None,
+            Tool::Shape => self.create_shape(self.active_shape_type, x, y, true).ok(),
+            Tool::Text => self.add_text("Text", x, y).ok(),
+            Tool::Image => self.add_image(String::new(), 160.0, 120.0, x, y).ok(),
+        }
+    }
+
+    pub fn last_insert_index(&self) -> Option<usize> {
+        self.last_insert_index
+    }
+
+    pub fn delete_element(&mut self, element_id: u32) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if self.document.is_element_locked(element_id) {
+            return false;
+        }
+        if let Some(command) = batch_command(self.remove_element(element_id)) {
+            self.record(command);
+            if self.selected_ids.contains(&element_id) {
+                self.selected_ids.retain(|&id| id != element_id);
+                if self.selected_ids.is_empty() {
+                    self.selected_ids.extend(self.document.find_first_shape());
+                }
+            }
+            self.sync_selection();
+            return true;
+        }
+        false
+    }
+
+    pub fn delete_selected(&mut self) -> usize {
+        if self.read_only {
+            return 0;
+        }
+        let mut commands = Vec::new();
+        let mut deleted = 0;
+        for element_id in std::mem::take(&mut self.selected_ids) {
+            if self.document.is_element_locked(element_id) {
+                self.selected_ids.push(element_id);
+                continue;
+            }
+            let removed = self.remove_element(element_id);
+            if !removed.is_empty() {
+                deleted += 1;
+                commands.extend(removed);
+            }
+        }
+        if !commands.is_empty() {
+            self.record(Command::Batch { commands });
+        }
+        if self.selected_ids.is_empty() {
+            self.selected_ids.extend(self.document.find_first_shape());
+        }
+        self.dirty = true;
+        deleted
+    }
+
+    pub fn move_element_to_layer(
+        &mut self,
+        element_id: u32,
+        target_layer_id: u32,
+        index: Option<usize>,
+    ) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if self.document.is_element_locked(element_id)
+            || self.document.is_layer_locked(target_layer_id)
+        {
+            return false;
+        }
+        if let Some((from_layer_id, from_index, to_index)) =
+            self.document
+                .move_element(element_id, target_layer_id, index)
+        {
+            self.record(Command::MoveElement {
+                element_id,
+                from_layer_id,
+                from_index,
+                to_layer_id: target_layer_id,
+                to_index,
+            });
+            return true;
+        }
+        false
+    }
+
+    pub fn update_element(&mut self, element_id: u32, props: JsValue) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let update = parse_update(props)?;
+        if self.document.is_element_locked(element_id) {
+            return Ok(false);
+        }
+        let before = self.document.related_elements(element_id);
+        if self.document.apply_update(element_id, &update)
+            && let Some(command) = self.related_command(before)
+        {
+            self.record(command);
+            if self.selected_ids.contains(&element_id) {
+                self.sync_selection();
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    pub fn set_element_locked(&mut self, element_id: u32, locked: bool) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if let Some((layer_id, index, before, after)) =
+            self.document.set_element_locked(element_id, locked)
+        {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+            if self.selected_ids.contains(&element_id) {
+                self.sync_selection();
+            }
+            return true;
+        }
+        false
+    }
+
+    pub fn set_element_anchor(
+        &mut self,
+        element_id: u32,
+        anchor: &str,
+    ) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let anchor = Anchor::from_name(anchor).ok_or(EngineError::UnknownAnchor)?;
+        if let Some((layer_id, index, before, after)) =
+            self.document.set_element_anchor(element_id, anchor)
+        {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    pub fn set_element_visible(&mut self, element_id: u32, visible: bool) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if let Some((layer_id, index, before, after)) =
+            self.document.set_element_visible(element_id, visible)
+        {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+            return true;
+        }
+        false
+    }
+
+    pub fn convert_element(&mut self, element_id: u32, kind: &str) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if self.document.is_element_locked(element_id) {
+            return false;
+        }
+        let changed = self.document.modify_element(element_id, |element| {
+            element.data = match (kind, &element.data) {
+                ("shape", ElementData::Shape(_))
+                | ("text", ElementData::Text(_))
+                | ("image", ElementData::Image(_)) => return false,
+                ("shape", _) => ElementData::Shape(ShapeElement::rectangle()),
+                ("text", _) => ElementData::Text(TextElement::new("Text")),
+                ("image", _) => ElementData::Image(ImageElement::new(String::new())),
+                _ => return false,
+            };
+            true
+        });
+        if let Some((layer_id, index, before, after)) = changed {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+            return true;
+        }
+        false
+    }
+
+    pub fn reset_crop(&mut self, element_id: u32) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if self.document.is_element_locked(element_id) {
+            return false;
+        }
+        let changed = self.document.modify_element(element_id, |element| match &mut element.data {
+            ElementData::Image(image) => image.crop.take().is_some(),
+            _ => false,
+        });
+        if let Some((layer_id, index, before, after)) = changed {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+            return true;
+        }
+        false
+    }
+
+    pub fn apply_filter(&mut self, element_id: u32, filter: &str, value: f32) -> bool {
+        if self.read_only || !value.is_finite() {
+            return false;
+        }
+        if self.document.is_element_locked(element_id) {
+            return false;
+        }
+        let location = self.document.find_element_location(element_id);
+        let (before, after) = {
+            let element = match self.document.get_element_by_id_mut(element_id) {
+                Some(element) => element,
+                None => return false,
+            };
+            let before = element.clone();
+            let updated = match &mut element.data {
+                ElementData::Image(image) => {
+                    match filter {
+                        "brightness" => image.filters.brightness = value.clamp(0.0, 2.0),
+                        "contrast" => image.filters.contrast = value.clamp(0.0, 2.0),
+                        "saturation" => image.filters.saturation = value.clamp(0.0, 2.0),
+                        _ => return false,
+                    }
+                    true
+                }
+                _ => false,
+            };
+            if !updated {
+                return false;
+            }
+            let after = element.clone();
+            (before, after)
+        };
+
+        if let Some((layer_id, index)) = location {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+        }
+        true
+    }
+
+    pub fn flip_selected(&mut self, axis: &str) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return Ok(false),
+        };
+        if self.document.is_element_locked(element_id) {
+            return Ok(false);
+        }
+        let location = self.document.find_element_location(element_id);
+        let element = match self.document.get_element_by_id_mut(element_id) {
+            Some(element) => element,
+            None => return Ok(false),
+        };
+        let before = element.clone();
+        match axis {
+            "horizontal" => element.transform.flip_horizontal(),
+            "vertical" => element.transform.flip_vertical(),
+            _ => return Err(EngineError::UnknownFlipAxis),
+        }
+        let after = element.clone();
+
+        if let Some((layer_id, index)) = location {
+            self.record(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+        }
+        Ok(true)
+    }
+
+    pub fn nudge_selected(&mut self, dx: f32, dy: f32) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return false,
+        };
+        if self.document.is_element_locked(element_id) || !dx.is_finite() || !dy.is_finite() {
+            return false;
+        }
+        let Some(mut transform) = self.document.get_element_transform(element_id) else {
+            return false;
+        };
+        let before = self.document.related_elements(element_id);
+        transform.x += dx;
+        transform.y += dy;
+        if !self.document.set_element_transform(element_id, transform) {
+            return false;
+        }
+        if let Some(command) = self.related_command(before) {
+            self.history
+                .record_coalesced(&format!("nudge:{element_id}"), command);
+        }
+        self.dirty = true;
+        true
+    }
+
+    pub fn end_nudge(&mut self) {
+        self.history.break_coalescing();
+    }
+
+    pub fn get_fill_hex(&self, element_id: u32) -> Option<String> {
+        let element = self.document.get_element_by_id(element_id)?;
+        let resolved = self.document.resolve_instance(element);
+        let color = match &resolved.as_ref().unwrap_or(element).data {
+            ElementData::Shape(shape) => shape.fill.as_ref()?.color,
+            ElementData::Text(text) => text.fill,
+            ElementData::Image(_) | ElementData::Instance(_) | ElementData::Group(_) => {
+                return None;
+            }
+        };
+        Some(color.to_hex())
+    }
+
+    pub fn get_element_bounds(&self, element_id: u32) -> Option<JsValue> {
+        let transform = self.document.get_element_transform(element_id)?;
+        serde_wasm_bindgen::to_value(&transform.bounds()).ok()
+    }
+
+    pub fn measure(&self, a: u32, b: u32) -> Option<JsValue> {
+        let from = self.document.get_element_transform(a)?;
+        let to = self.document.get_element_transform(b)?;
+        serde_wasm_bindgen::to_value(&from.measure_to(&to)).ok()
+    }
+
+    pub fn export_render_list(&self) -> Result<JsValue, EngineError> {
+        let mut shapes = Vec::new();
+        for layer in self.document.layers.iter().filter(|layer| layer.visible) {
+            for element in layer.elements.iter().filter(|element| element.visible) {
+                push_element_shape(
+                    &self.document,
+                    layer,
+                    element,
+                    self.default_fill,
+                    &mut shapes,
+                );
+            }
+        }
+        serde_wasm_bindgen::to_value(&shapes)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn get_content_bounds(&self) -> JsValue {
+        self.document
+            .content_bounds()
+            .and_then(|bounds| serde_wasm_bindgen::to_value(&bounds).ok())
+            .unwrap_or(JsValue::NULL)
+    }
+
+    pub fn find_overlaps(&self) -> Result<JsValue, EngineError> {
+        serde_wasm_bindgen::to_value(&self.document.find_overlaps())
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn find_by_name(&self, query: &str) -> Vec<u32> {
+        self.document.find_by_name(query)
+    }
+
+    pub fn get_selected_id(&self) -> Option<u32> {
+        self.selected_element_id()
+    }
+
+    pub fn selected_ids(&self) -> Vec<u32> {
+        self.selected_ids.clone()
+    }
+
+    pub fn add_to_selection(&mut self, element_id: u32) -> bool {
+        if self.selected_ids.contains(&element_id)
+            || self.document.get_element_by_id(element_id).is_none()
+        {
+            return false;
+        }
+        self.selected_ids.push(element_id);
+        self.dirty = true;
+        true
+    }
+
+    pub fn remove_from_selection(&mut self, element_id: u32) -> bool {
+        let count = self.selected_ids.len();
+        self.selected_ids.retain(|&id| id != element_id);
+        let removed = self.selected_ids.len() != count;
+        self.dirty |= removed;
+        removed
+    }
+
+    pub fn clear_selection(&mut self) -> bool {
+        self.dirty = true;
+        let had_selection = !self.selected_ids.is_empty();
+        self.selected_ids.clear();
+        had_selection
+    }
+
+    pub fn set_active_tool(&mut self, tool: &str) -> Result<(), EngineError> {
+        self.active_tool = match tool {
+            "select" => Tool::Select,
+            "shape" => Tool::Shape,
+            "text" => Tool::Text,
+            "image" => Tool::Image,
+            _ => return Err(EngineError::UnknownTool),
+        };
+        Ok(())
+    }
+
+    pub fn set_active_shape(&mut self, shape_type: &str) -> Result<(), EngineError> {
+        self.active_shape_type = parse_shape_type(shape_type)?;
+        Ok(())
+    }
+
+    pub fn select_at(&mut self, x: f32, y: f32) -> Option<u32> {
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        let hit = self.hit_test(x, y);
+        self.selected_ids = hit.into_iter().collect();
+        self.dirty = true;
+        hit
+    }
+
+    pub fn handle_at(&self, x: f32, y: f32) -> Option<u8> {
+        // Handles resize a single element, so a multi-selection exposes none.
+        if self.selected_ids.len() > 1 {
+            return None;
+        }
+        let element = self.document.get_element_by_id(self.selected_element_id()?)?;
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        let transform = element.transform;
+        let (lx, ly) = transform.local_point(x, y);
+        let handle_half = self.selection_style.handle_size * 0.5;
+        transform
+            .local_corners()
+            .iter()
+            .position(|(cx, cy)| (lx - cx).abs() <= handle_half && (ly - cy).abs() <= handle_half)
+            .map(|index| index as u8)
+    }
+
+    pub fn point_in_canvas(&self, x: f32, y: f32) -> bool {
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        let canvas = &self.document.canvas;
+        (0.0..=canvas.width as f32).contains(&x) && (0.0..=canvas.height as f32).contains(&y)
+    }
+
+    pub fn pointer_hit(&self, x: f32, y: f32) -> Result<JsValue, EngineError> {
+        let hit = match self.handle_at(x, y) {
+            Some(index) => PointerHit::Handle { index },
+            None => {
+                let (x, y) = self.viewport.screen_to_document(x, y);
+                match self.hit_test(x, y) {
+                    Some(id) => PointerHit::Element { id },
+                    None => PointerHit::Empty,
+                }
+            }
+        };
+        serde_wasm_bindgen::to_value(&hit)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn element_path_data(&self, element_id: u32) -> Option<String> {
+        self.document.element_path_data(element_id)
+    }
+
+    pub fn point_in_selection(&self, x: f32, y: f32) -> bool {
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        self.selected_ids
+            .iter()
+            .any(|&id| self.document.element_contains_point(id, x, y))
+    }
+
+    // Selects every hit with the front-most as primary and returns them front-most first.
+    pub fn select_in_rect(&mut self, x: f32, y: f32, width: f32, height: f32) -> Vec<u32> {
+        let (x0, y0) = self.viewport.screen_to_document(x, y);
+        let (x1, y1) = self.viewport.screen_to_document(x + width, y + height);
+        let hits = self.document.hit_test_rect(Bounds {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            width: (x1 - x0).abs(),
+            height: (y1 - y0).abs(),
+        });
+        let mut selected = Vec::new();
+        for id in hits {
+            let id = self.document.selectable_ancestor(id, self.entered_group);
+            if !selected.contains(&id) {
+                selected.push(id);
+            }
+        }
+        self.selected_ids = selected.iter().rev().copied().collect();
+        self.dirty = true;
+        selected
+    }
+
+    pub fn hit_test_all(&self, x: f32, y: f32) -> Vec<u32> {
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        self.document.hit_test_all(x, y)
+    }
+
+    pub fn group_selected(&mut self) -> Option<u32> {
+        if self.read_only {
+            return None;
+        }
+        let children: Vec<u32> = self
+            .selected_ids
+            .iter()
+            .copied()
+            .filter(|&id| {
+                !self.document.is_element_locked(id) && self.document.owning_group(id).is_none()
+            })
+            .collect();
+        if children.len() < 2 {
+            return None;
+        }
+        let bounds = children
+            .iter()
+            .filter_map(|&id| self.document.get_element_transform(id))
+            .map(|transform| transform.bounds())
+            .reduce(|combined, bounds| combined.union(&bounds))?;
+        let transform = Transform2D::new(
+            bounds.x,
+            bounds.y,
+            bounds.width.max(1.0),
+            bounds.height.max(1.0),
+        );
+        let element_id = self.document.next_element_id();
+        let group = ElementData::Group(GroupElement::new(children));
+        let element = Element::new(element_id, "Group", transform, group);
+        self.insert_new_element(element, true).ok()
+    }
+
+    pub fn ungroup(&mut self, group_id: u32) -> bool {
+        if self.read_only || self.document.is_element_locked(group_id) {
+            return false;
+        }
+        let children = self.document.group_children(group_id);
+        if children.is_empty() {
+            return false;
+        }
+        // The group's owner, if any, adopts its children in its place.
+        let Some(command) = batch_command(self.remove_element(group_id)) else {
+            return false;
+        };
+        self.record(command);
+        self.selected_ids = children;
+        self.sync_selection();
+        true
+    }
+
+    // An empty direction turns auto-layout off and leaves the children where they are.
+    pub fn set_group_layout(
+        &mut self,
+        group_id: u32,
+        direction: &str,
+        spacing: f32,
+        padding: f32,
+    ) -> Result<bool, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        if !spacing.is_finite() || !padding.is_finite() {
+            return Err(EngineError::InvalidValue("Spacing and padding must be finite"));
+        }
+        let layout = match direction {
+            "" => None,
+            _ => Some(GroupLayout {
+                direction: LayoutDirection::from_name(direction)
+                    .ok_or(EngineError::UnknownLayoutDirection)?,
+                spacing,
+                padding: padding.max(0.0),
+            }),
+        };
+        if !matches!(
+            self.document.get_element_by_id(group_id).map(|element| &element.data),
+            Some(ElementData::Group(_))
+        ) {
+            return Err(EngineError::ElementNotFound);
+        }
+        if self.document.is_element_locked(group_id) {
+            return Ok(false);
+        }
+        let before = self.document.related_elements(group_id);
+        if !self.document.set_group_layout(group_id, layout) {
+            return Ok(false);
+        }
+        if let Some(command) = self.related_command(before) {
+            self.record(command);
+        }
+        Ok(true)
+    }
+
+    // Inside an entered group, clicks select its direct children instead of the group.
+    pub fn enter_group(&mut self, group_id: u32) -> bool {
+        if self.document.group_children(group_id).is_empty() {
+            return false;
+        }
+        self.entered_group = Some(group_id);
+        true
+    }
+
+    pub fn exit_group(&mut self) -> Option<u32> {
+        self.entered_group.take()
+    }
+
+    pub fn deselect(&mut self) -> bool {
+        self.clear_selection()
+    }
+
+    pub fn select_element(&mut self, element_id: u32) -> bool {
+        if self.document.get_element_by_id(element_id).is_some() {
+            self.selected_ids = vec![element_id];
+            self.dirty = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn begin_transform(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return false,
+        };
+        let mut related = self.document.related_elements(element_id);
+        if related.is_empty() {
+            return false;
+        }
+        let before = related.remove(0);
+        self.transform_snapshot = Some(TransformSnapshot {
+            element_id,
+            before,
+            related,
+        });
+        true
+    }
+
+    pub fn update_selected_transform(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return false,
+        };
+        if self.document.is_element_locked(element_id) {
+            return false;
+        }
+        let (width, height) = match &self.transform_snapshot {
+            Some(snapshot) if self.aspect_lock && snapshot.element_id == element_id => {
+                lock_aspect(width, height, &snapshot.before.transform)
+            }
+            _ => (width, height),
+        };
+        let mut transform = match self.document.get_element_transform(element_id) {
+            Some(transform) => transform,
+            None => return false,
+        };
+        transform.x = x;
+        transform.y = y;
+        transform.width = width.max(1.0);
+        transform.height = height.max(1.0);
+        if self.snap_to_guides || self.smart_snap || self.snap_to_canvas {
+            let (snapped, lines) = self.document.snap_transform(
+                element_id,
+                transform,
+                self.snap_to_guides,
+                self.smart_snap,
+                self.snap_to_canvas,
+                self.snap_threshold,
+            );
+            transform = snapped;
+            self.snap_lines = lines;
+        }
+        if self.clamp_to_canvas {
+            transform = self.document.clamp_to_canvas(transform);
+        }
+        self.dirty = true;
+        self.apply_selected_transform(element_id, transform)
+    }
+
+    pub fn resize_from_handle(&mut self, handle: u8, x: f32, y: f32) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return false,
+        };
+        if handle > 3 || self.document.is_element_locked(element_id) {
+            return false;
+        }
+        let base = match &self.transform_snapshot {
+            Some(snapshot) if snapshot.element_id == element_id => snapshot.before.transform,
+            _ => match self.document.get_element_transform(element_id) {
+                Some(transform) => transform,
+                None => return false,
+            },
+        };
+        let corner = handle as usize;
+        let mut transform = base.resized_from_corner(corner, x, y);
+        if self.constrain {
+            let size = transform.width.max(transform.height);
+            transform = base.resized_from_anchor(corner, size, size);
+        } else if self.aspect_lock {
+            let (width, height) = lock_aspect(transform.width, transform.height, &base);
+            transform = base.resized_from_anchor(corner, width.max(1.0), height.max(1.0));
+        }
+        if self.clamp_to_canvas {
+            transform = self.document.clamp_to_canvas(transform);
+        }
+        self.dirty = true;
+        self.apply_selected_transform(element_id, transform)
+    }
+
+    pub fn rotate_selected_to(&mut self, pointer_x: f32, pointer_y: f32) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let (pointer_x, pointer_y) = self.viewport.screen_to_document(pointer_x, pointer_y);
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return false,
+        };
+        if self.document.is_element_locked(element_id) {
+            return false;
+        }
+        let mut transform = match self.document.get_element_transform(element_id) {
+            Some(transform) => transform,
+            None => return false,
+        };
+        let (cx, cy) = transform.pivot_point();
+        // Zero rotation points the pointer straight up from the pivot.
+        let mut rotation = (pointer_y - cy).atan2(pointer_x - cx) + std::f32::consts::FRAC_PI_2;
+        if self.rotation_snap {
+            let step = ROTATION_SNAP_DEGREES.to_radians();
+            rotation = (rotation / step).round() * step;
+        }
+        transform.rotation = rotation;
+        self.dirty = true;
+        self.apply_selected_transform(element_id, transform)
+    }
+
+    pub fn set_rotation_snap(&mut self, enabled: bool) {
+        self.rotation_snap = enabled;
+    }
+
+    pub fn set_clamp_to_canvas(&mut self, enabled: bool) {
+        self.clamp_to_canvas = enabled;
+    }
+
+    pub fn add_guide(&mut self, orientation: &str, position: f32) -> Result<usize, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let orientation = GuideOrientation::from_name(orientation)
+            .ok_or(EngineError::UnknownGuideOrientation)?;
+        if !position.is_finite() {
+            return Err(EngineError::InvalidValue("Guide position must be finite"));
+        }
+        self.dirty = true;
+        Ok(self.document.add_guide(Guide::new(orientation, position)))
+    }
+
+    pub fn remove_guide(&mut self, index: usize) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let removed = self.document.remove_guide(index).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    pub fn clear_guides(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let cleared = self.document.clear_guides();
+        self.dirty |= cleared;
+        cleared
+    }
+
+    pub fn add_palette_color(&mut self, hex: &str) -> Result<usize, EngineError> {
+        if self.read_only {
+            return Err(EngineError::ReadOnly);
+        }
+        let color = Color::from_hex(hex).map_err(EngineError::InvalidColor)?;
+        self.dirty = true;
+        Ok(self.document.add_palette_color(color))
+    }
+
+    pub fn remove_palette_color(&mut self, index: usize) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let removed = self.document.remove_palette_color(index).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    pub fn get_palette(&self) -> Result<JsValue, EngineError> {
+        serde_wasm_bindgen::to_value(&self.document.palette)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn set_snap_to_guides(&mut self, enabled: bool) {
+        self.snap_to_guides = enabled;
+    }
+
+    pub fn set_smart_snap(&mut self, enabled: bool) {
+        self.smart_snap = enabled;
+        if !enabled {
+            self.snap_lines.clear();
+        }
+    }
+
+    pub fn set_snap_to_canvas(&mut self, enabled: bool) {
+        self.snap_to_canvas = enabled;
+        if !enabled {
+            self.snap_lines.clear();
+        }
+    }
+
+    pub fn set_snap_threshold(&mut self, threshold: f32) {
+        self.snap_threshold = threshold.max(0.0);
+    }
+
+    pub fn get_snap_lines(&self) -> Result<JsValue, EngineError> {
+        serde_wasm_bindgen::to_value(&self.snap_lines)
+            .map_err(|err| EngineError::Serialization(err.to_string()))
+    }
+
+    pub fn set_aspect_lock(&mut self, enabled: bool) {
+        self.aspect_lock = enabled;
+    }
+
+    pub fn set_constrain(&mut self, enabled: bool) {
+        self.constrain = enabled;
+    }
+
+    pub fn update_selected_text_size(&mut self, font_size: f32) -> bool {
+        if self.read_only || !font_size.is_finite() {
+            return false;
+        }
+        let element_id = match self.selected_element_id() {
+            Some(element_id) => element_id,
+            None => return false,
         };
-        if snapshot.before.transform == after.transform {
+        if self.document.is_element_locked(element_id) {
             return false;
         }
-        if let Some((layer_id, index)) = self.document.find_element_location(snapshot.element_id) {
-            self.history.record(Command::UpdateElement {
-                layer_id,
-                index,
-                before: snapshot.before,
-                after,
-            });
+        if let Some(element) = self.document.get_element_by_id_mut(element_id)
+            && let ElementData::Text(text) = &mut element.data
+        {
+            text.font_size = font_size.max(1.0);
+            self.dirty = true;
             return true;
         }
         false
     }
+
+    pub fn commit_transform(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        self.snap_lines.clear();
+        let snapshot = match self.transform_snapshot.take() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        let after = match self.document.get_element_by_id(snapshot.element_id) {
+            Some(element) => element.clone(),
+            None => return false,
+        };
+        if snapshot.before.transform == after.transform {
+            return false;
+        }
+        let mut before = vec![snapshot.before];
+        before.extend(snapshot.related);
+        match self.related_command(before) {
+            Some(command) => {
+                self.record(command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn begin_create(&mut self, x: f32, y: f32) -> Option<u32> {
+        if self.read_only {
+            return None;
+        }
+        self.cancel_create();
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        let transform = Transform2D::new(x, y, 0.0, 0.0);
+        if !transform.is_finite() {
+            return None;
+        }
+        let element = self.tool_element(transform)?;
+        let element_id = element.id;
+        let layer_id = self.document.active_layer_id;
+        self.document.push_element(layer_id, element)?;
+        self.create_session = Some(CreateSession {
+            element_id,
+            origin_x: x,
+            origin_y: y,
+        });
+        self.selected_ids = vec![element_id];
+        self.sync_selection();
+        Some(element_id)
+    }
+
+    pub fn update_create(&mut self, x: f32, y: f32) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let (x, y) = self.viewport.screen_to_document(x, y);
+        let session = match self.create_session {
+            Some(session) => session,
+            None => return false,
+        };
+        let mut width = (x - session.origin_x).abs();
+        let mut height = (y - session.origin_y).abs();
+        if self.constrain {
+            width = width.max(height);
+            height = width;
+        }
+        let mut transform = Transform2D::new(
+            if x < session.origin_x { session.origin_x - width } else { session.origin_x },
+            if y < session.origin_y { session.origin_y - height } else { session.origin_y },
+            width,
+            height,
+        );
+        if self.clamp_to_canvas {
+            transform = self.document.clamp_to_canvas(transform);
+        }
+        self.dirty = true;
+        self.document.set_element_transform(session.element_id, transform)
+    }
+
+    pub fn commit_create(&mut self) -> Option<u32> {
+        if self.read_only {
+            return None;
+        }
+        let session = self.create_session.take()?;
+        let (layer_id, index) = self.document.find_element_location(session.element_id)?;
+        let element = self.document.get_element_by_id_mut(session.element_id)?;
+        element.transform.width = element.transform.width.max(1.0);
+        element.transform.height = element.transform.height.max(1.0);
+        let element = element.clone();
+        self.record(Command::AddElement {
+            layer_id,
+            index,
+            element,
+        });
+        Some(session.element_id)
+    }
+
+    pub fn cancel_create(&mut self) -> bool {
+        let session = match self.create_session.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        self.document.remove_element_by_id(session.element_id);
+        self.selected_ids.retain(|&id| id != session.element_id);
+        self.dirty = true;
+        true
+    }
+}
+
+fn parse_shape_type(shape_type: &str) -> Result<ShapeType, EngineError> {
+    ShapeType::from_name(shape_type).ok_or(EngineError::UnknownShapeType)
+}
+
+fn parse_update(props: JsValue) -> Result<ElementUpdate, EngineError> {
+    let update: ElementUpdate = serde_wasm_bindgen::from_value(props)
+        .map_err(|err| EngineError::InvalidInput(err.to_string()))?;
+    validate_update(&update)?;
+    Ok(update)
+}
+
+fn validate_update(update: &ElementUpdate) -> Result<(), EngineError> {
+    if let Some(shape_type) = &update.shape_type {
+        parse_shape_type(shape_type)?;
+    }
+    if let Some(align) = &update.align
+        && TextAlign::from_name(align).is_none()
+    {
+        return Err(EngineError::UnknownTextAlign);
+    }
+    if !update.is_finite() {
+        return Err(EngineError::InvalidValue("Update values must be finite"));
+    }
+    if let Some(hex) = &update.fill_hex {
+        Color::from_hex(hex).map_err(EngineError::InvalidColor)?;
+    }
+    Ok(())
+}
+
+fn batch_command(mut commands: Vec<Command>) -> Option<Command> {
+    match commands.len() {
+        0 => None,
+        1 => commands.pop(),
+        _ => Some(Command::Batch { commands }),
+    }
+}
+
+fn lock_aspect(width: f32, height: f32, reference: &Transform2D) -> (f32, f32) {
+    if reference.width <= 0.0 || reference.height <= 0.0 {
+        return (width, height);
+    }
+    let ratio = reference.width / reference.height;
+    let width_change = ((width - reference.width) / reference.width).abs();
+    let height_change = ((height - reference.height) / reference.height).abs();
+    if width_change >= height_change {
+        (width, width / ratio)
+    } else {
+        (height * ratio, height)
+    }
+}
+
+fn performance_now() -> Option<f64> {
+    Some(web_sys::window()?.performance()?.now())
+}
+
+fn transform_rect(transform: &Transform2D) -> Rect {
+    Rect {
+        x: transform.x,
+        y: transform.y,
+        width: transform.width,
+        height: transform.height,
+    }
+}
+
+fn push_layer_shapes(
+    document: &Document,
+    layer: &Layer,
+    visible_area: &Bounds,
+    selected_id: Option<u32>,
+    default_fill: Color,
+    rects: &mut Vec<RenderShape>,
+) -> Option<Selection> {
+    let mut selected_rect = None;
+
+    for element in &layer.elements {
+        if !element.visible {
+            continue;
+        }
+        if Some(element.id) == selected_id {
+            let transform = &element.transform;
+            selected_rect = Some(Selection {
+                rect: transform_rect(transform),
+                rotation: transform.rotation,
+                pivot: [transform.pivot.0, transform.pivot.1],
+            });
+        }
+        if !element.visual_bounds().intersects(visible_area) {
+            continue;
+        }
+        push_element_shape(document, layer, element, default_fill, rects);
+    }
+    selected_rect
 }
 
-fn parse_shape_type(shape_type: &str) -> Result<ShapeType, JsValue> {
-    match shape_type {
-        "rect" | "rectangle" => Ok(ShapeType::Rect),
-        "ellipse" => Ok(ShapeType::Ellipse),
-        "line" => Ok(ShapeType::Line),
-        "polygon" => Ok(ShapeType::Polygon),
-        _ => Err(JsValue::from_str("Unknown shape type")),
+fn push_element_shape(
+    document: &Document,
+    layer: &Layer,
+    element: &Element,
+    default_fill: Color,
+    rects: &mut Vec<RenderShape>,
+) {
+    let resolved = document.resolve_instance(element);
+    let element = resolved.as_ref().unwrap_or(element);
+    let transform = element.transform;
+    let rect = transform_rect(&transform);
+    // Textured images and text only take their alpha from the instance color.
+    let opacity = layer.opacity * element.opacity;
+    let color = [1.0, 1.0, 1.0, opacity];
+    let shadow = element.shadow.map(|shadow| RenderShadow {
+        offset_x: shadow.offset_x,
+        offset_y: shadow.offset_y,
+        blur: shadow.blur,
+        color: [
+            shadow.color.r,
+            shadow.color.g,
+            shadow.color.b,
+            shadow.color.a * opacity,
+        ],
+    });
+    match &element.data {
+        ElementData::Shape(shape) => {
+            let shape_kind = match shape.shape_type {
+                ShapeType::Rect => ShapeKind::Rect,
+                ShapeType::Ellipse => ShapeKind::Ellipse,
+                ShapeType::Polygon => ShapeKind::Polygon,
+                ShapeType::Line => ShapeKind::Line,
+            };
+            let fill = shape.fill.map_or(default_fill, |fill| fill.color);
+            rects.push(RenderShape {
+                rect,
+                color: [fill.r, fill.g, fill.b, fill.a * opacity],
+                shape: shape_kind,
+                sides: shape.sides,
+                corner_radius: shape.corner_radius,
+                flip_x: transform.scale_x < 0.0,
+                flip_y: transform.scale_y < 0.0,
+                rotation: transform.rotation,
+                pivot: [transform.pivot.0, transform.pivot.1],
+                stroke: shape.stroke.map(|stroke| RenderStroke {
+                    color: [
+                        stroke.color.r,
+                        stroke.color.g,
+                        stroke.color.b,
+                        stroke.color.a * opacity,
+                    ],
+                    width: stroke.width,
+                }),
+                image: None,
+                text: None,
+                shadow,
+                blend: layer.blend_mode,
+            });
+        }
+        ElementData::Image(image) => {
+            rects.push(RenderShape {
+                rect,
+                color,
+                shape: ShapeKind::Rect,
+                sides: 4,
+                corner_radius: 0.0,
+                flip_x: transform.scale_x < 0.0,
+                flip_y: transform.scale_y < 0.0,
+                rotation: transform.rotation,
+                pivot: [transform.pivot.0, transform.pivot.1],
+                stroke: None,
+                image: Some(RenderImage {
+                    source: image.source.clone(),
+                    brightness: image.filters.brightness,
+                    contrast: image.filters.contrast,
+                    saturation: image.filters.saturation,
+                    crop: image
+                        .crop
+                        .map(|crop| [crop.x, crop.y, crop.width, crop.height]),
+                }),
+                text: None,
+                shadow,
+                blend: layer.blend_mode,
+            });
+        }
+        ElementData::Text(text) => {
+            let fill = text.fill;
+            rects.push(RenderShape {
+                rect,
+                color,
+                shape: ShapeKind::Rect,
+                sides: 4,
+                corner_radius: 0.0,
+                flip_x: transform.scale_x < 0.0,
+                flip_y: transform.scale_y < 0.0,
+                rotation: transform.rotation,
+                pivot: [transform.pivot.0, transform.pivot.1],
+                stroke: None,
+                image: None,
+                text: Some(RenderText {
+                    content: text.content.clone(),
+                    font_family: text.font_family.clone(),
+                    font_size: text.font_size,
+                    color: [fill.r, fill.g, fill.b, fill.a],
+                    align: text.align,
+                    line_height: text.line_height,
+                    letter_spacing: text.letter_spacing,
+                    stroke: text.stroke.map(|stroke| RenderStroke {
+                        color: [
+                            stroke.color.r,
+                            stroke.color.g,
+                            stroke.color.b,
+                            stroke.color.a,
+                        ],
+                        width: stroke.width,
+                    }),
+                }),
+                shadow,
+                blend: layer.blend_mode,
+            });
+        }
+        ElementData::Instance(_) | ElementData::Group(_) => {}
     }
 }
 
 impl PigmoraEngine {
-    fn collect_rects(&self) -> (Vec<RenderShape>, Option<Rect>) {
-        let mut rects = Vec::new();
+    fn with_renderer(renderer: Renderer) -> Self {
+        PigmoraEngine {
+            renderer,
+            document: Document::new(0, 0),
+            history: History::new(),
+            selected_ids: Vec::new(),
+            active_tool: Tool::Select,
+            active_shape_type: ShapeType::Rect,
+            transform_snapshot: None,
+            aspect_lock: false,
+            clamp_to_canvas: false,
+            dirty: true,
+            render_shapes: Vec::new(),
+            marquee: None,
+            selection_style: SelectionStyle::default(),
+            rotation_snap: false,
+            create_session: None,
+            constrain: false,
+            guide_rects: Vec::new(),
+            snap_to_guides: false,
+            smart_snap: false,
+            snap_to_canvas: false,
+            snap_threshold: DEFAULT_SNAP_THRESHOLD,
+            snap_lines: Vec::new(),
+            last_insert_index: None,
+            read_only: false,
+            deterministic: false,
+            render_on_resize: false,
+            default_fill: Color::new(0.86, 0.42, 0.25, 1.0),
+            render_throttle: false,
+            last_render_at: None,
+            dropped_renders: 0,
+            viewport: Viewport::new(0.0, 0.0),
+            entered_group: None,
+        }
+    }
+
+    #[cfg(test)]
+    fn headless() -> Self {
+        Self::with_renderer(Renderer::headless())
+    }
+
+    fn record(&mut self, command: Command) {
+        self.history.record(command);
+        self.dirty = true;
+    }
+
+    fn update_commands(&self, before: Vec<Element>) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for before in before {
+            let Some((layer_id, index)) = self.document.find_element_location(before.id) else {
+                continue;
+            };
+            let Some(after) = self.document.get_element_by_id(before.id).cloned() else {
+                continue;
+            };
+            commands.push(Command::UpdateElement {
+                layer_id,
+                index,
+                before,
+                after,
+            });
+        }
+        commands
+    }
+
+    // Group edits move descendants and refit owners, so each touched element gets an update.
+    fn related_command(&self, mut before: Vec<Element>) -> Option<Command> {
+        let primary_id = before.first()?.id;
+        before.retain(|element| {
+            element.id == primary_id
+                || self.document.get_element_transform(element.id) != Some(element.transform)
+        });
+        batch_command(self.update_commands(before))
+    }
+
+    // Removing an element also rewrites the group that owned it, so both land in one command.
+    fn remove_element(&mut self, element_id: u32) -> Vec<Command> {
+        let Some((layer_id, index, element)) = self.document.remove_element_by_id(element_id)
+        else {
+            return Vec::new();
+        };
+        let adopted = match &element.data {
+            ElementData::Group(group) => group.children.clone(),
+            _ => Vec::new(),
+        };
+        let touched = self.document.detach_from_group(element_id, adopted);
+        if self.entered_group == Some(element_id) {
+            self.entered_group = None;
+        }
+        let mut commands = vec![Command::DeleteElement {
+            layer_id,
+            index,
+            element,
+        }];
+        commands.extend(self.update_commands(touched));
+        commands
+    }
+
+    // Followers are re-derived from the drag snapshot so repeated updates don't accumulate.
+    fn apply_selected_transform(&mut self, element_id: u32, transform: Transform2D) -> bool {
+        if !transform.is_finite() {
+            return false;
+        }
+        if let Some(snapshot) = &self.transform_snapshot
+            && snapshot.element_id == element_id
+        {
+            self.document.restore_elements(&snapshot.related);
+            if let Some(element) = self.document.get_element_by_id_mut(element_id) {
+                element.transform = snapshot.before.transform;
+            }
+        }
+        self.document.set_element_transform(element_id, transform)
+    }
+
+    fn create_shape(
+        &mut self,
+        shape_type: ShapeType,
+        x: f32,
+        y: f32,
+        select: bool,
+    ) -> Result<u32, EngineError> {
+        let transform = Transform2D::new(x, y, 160.0, 120.0);
+        let element_id = self.document.next_element_id();
+        let shape = ShapeElement {
+            shape_type,
+            ..ShapeElement::rectangle()
+        };
+        let element = document::Element::shape(element_id, "Shape", shape, transform);
+        self.insert_new_element(element, select)
+    }
+
+    fn create_text(
+        &mut self,
+        content: &str,
+        x: f32,
+        y: f32,
+        select: bool,
+    ) -> Result<u32, EngineError> {
+        let transform = Transform2D::new(x, y, 240.0, 80.0);
+        let element_id = self.document.next_element_id();
+        let text = TextElement::new(content);
+        let element = document::Element::text(element_id, "Text", text, transform);
+        self.insert_new_element(element, select)
+    }
+
+    fn create_image(
+        &mut self,
+        source: String,
+        width: f32,
+        height: f32,
+        x: f32,
+        y: f32,
+        select: bool,
+    ) -> Result<u32, EngineError> {
+        let transform = Transform2D::new(x, y, width.max(1.0), height.max(1.0));
+        let element_id = self.document.next_element_id();
+        let image = ImageElement::new(source);
+        let element = document::Element::image(element_id, "Image", image, transform);
+        self.insert_new_element(element, select)
+    }
+
+    fn tool_element(&mut self, transform: Transform2D) -> Option<Element> {
+        let (name, data) = match self.active_tool {
+            Tool::Select => return None,
+            Tool::Shape => {
+                let shape = ShapeElement {
+                    shape_type: self.active_shape_type,
+                    ..ShapeElement::rectangle()
+                };
+                ("Shape", ElementData::Shape(shape))
+            }
+            Tool::Text => ("Text", ElementData::Text(TextElement::new("Text"))),
+            Tool::Image => ("Image", ElementData::Image(ImageElement::new(String::new()))),
+        };
+        let element_id = self.document.next_element_id();
+        let mut element = Element::new(element_id, name, transform, data);
+        self.pin_name(&mut element);
+        Some(element)
+    }
+
+    fn pin_name(&self, element: &mut Element) {
+        if self.deterministic {
+            element.name = format!("{} {}", element.name, element.id);
+        }
+    }
+
+    fn insert_new_element(
+        &mut self,
+        mut element: Element,
+        select: bool,
+    ) -> Result<u32, EngineError> {
+        if !element.transform.is_finite() {
+            return Err(EngineError::InvalidValue("Transform must be finite"));
+        }
+        self.pin_name(&mut element);
+        let element_id = element.id;
+        let layer_id = self.document.active_layer_id;
+        let index = self
+            .document
+            .push_element(layer_id, element.clone())
+            .ok_or(EngineError::LayerNotFound)?;
+        self.record(Command::AddElement {
+            layer_id,
+            index,
+            element,
+        });
+        self.last_insert_index = Some(index);
+        if select {
+            self.selected_ids = vec![element_id];
+            self.sync_selection();
+        }
+        Ok(element_id)
+    }
+
+    fn collect_rects(&mut self) -> Option<Selection> {
+        let mut rects = std::mem::take(&mut self.render_shapes);
+        rects.clear();
         let mut selected_rect = None;
-        let selected_id = self.selected_element_id;
+        let visible_area = self.visible_area();
 
         for layer in &self.document.layers {
             if !layer.visible {
                 continue;
             }
-            for element in &layer.elements {
-                let transform = element.transform;
-                let rect = Rect {
-                    x: transform.x,
-                    y: transform.y,
-                    width: transform.width,
-                    height: transform.height,
-                };
-                if let ElementData::Shape(shape) = &element.data {
-                    let shape_kind = match shape.shape_type {
-                        ShapeType::Rect => ShapeKind::Rect,
-                        ShapeType::Ellipse => ShapeKind::Ellipse,
-                        ShapeType::Polygon => ShapeKind::Diamond,
-                        ShapeType::Line => ShapeKind::Rect,
-                    };
-                    rects.push(RenderShape {
-                        rect,
-                        shape: shape_kind,
-                    });
-                }
-                if Some(element.id) == selected_id {
-                    selected_rect = Some(rect);
-                }
-            }
+            let selected = push_layer_shapes(
+                &self.document,
+                layer,
+                &visible_area,
+                self.selected_element_id(),
+                self.default_fill,
+                &mut rects,
+            );
+            selected_rect = selected_rect.or(selected);
+        }
+
+        self.render_shapes = rects;
+        if self.selected_ids.len() > 1 {
+            return self.combined_selection();
         }
+        selected_rect
+    }
+
+    fn combined_selection(&self) -> Option<Selection> {
+        let bounds = self
+            .selected_ids
+            .iter()
+            .filter_map(|&id| self.document.get_element_transform(id))
+            .map(|transform| transform.bounds())
+            .reduce(|combined, bounds| combined.union(&bounds))?;
+        Some(Selection {
+            rect: Rect {
+                x: bounds.x,
+                y: bounds.y,
+                width: bounds.width,
+                height: bounds.height,
+            },
+            rotation: 0.0,
+            pivot: [0.5, 0.5],
+        })
+    }
+
+    fn collect_guides(&mut self) {
+        let canvas = &self.document.canvas;
+        self.guide_rects.clear();
+        self.guide_rects
+            .extend(self.document.guides.iter().map(|guide| match guide.orientation {
+                GuideOrientation::Horizontal => Rect {
+                    x: 0.0,
+                    y: guide.position - 0.5,
+                    width: canvas.width as f32,
+                    height: 1.0,
+                },
+                GuideOrientation::Vertical => Rect {
+                    x: guide.position - 0.5,
+                    y: 0.0,
+                    width: 1.0,
+                    height: canvas.height as f32,
+                },
+            }));
+    }
+
+    fn visible_area(&self) -> Bounds {
+        self.viewport.visible_bounds()
+    }
+
+    fn hit_test(&self, x: f32, y: f32) -> Option<u32> {
+        let hit = self.document.hit_test(x, y)?;
+        Some(self.document.selectable_ancestor(hit, self.entered_group))
+    }
 
-        (rects, selected_rect)
+    // The most recently selected element is the primary one that tools act on.
+    fn selected_element_id(&self) -> Option<u32> {
+        self.selected_ids.last().copied()
     }
 
     fn sync_selection(&mut self) {
-        let element_id = match self.selected_element_id {
-            Some(element_id) => element_id,
-            None => return,
+        if self.selected_ids.is_empty() {
+            return;
+        }
+        let document = &self.document;
+        self.selected_ids
+            .retain(|&id| document.get_element_transform(id).is_some());
+        if self.selected_ids.is_empty() {
+            self.selected_ids.extend(self.document.find_first_shape());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aspect_lock_keeps_the_snapshot_ratio() {
+        let mut engine = PigmoraEngine::headless();
+        let element_id = engine.add_shape("rect", 0.0, 0.0).unwrap();
+        engine.set_aspect_lock(true);
+        assert!(engine.begin_transform());
+        assert!(engine.update_selected_transform(0.0, 0.0, 320.0, 120.0));
+        let transform = engine.document.get_element_transform(element_id).unwrap();
+        assert_eq!((transform.width, transform.height), (320.0, 240.0));
+    }
+
+    #[test]
+    fn aspect_lock_is_ignored_without_a_transform() {
+        let mut engine = PigmoraEngine::headless();
+        let element_id = engine.add_shape("rect", 0.0, 0.0).unwrap();
+        engine.set_aspect_lock(true);
+        assert!(engine.update_selected_transform(0.0, 0.0, 320.0, 120.0));
+        let transform = engine.document.get_element_transform(element_id).unwrap();
+        assert_eq!((transform.width, transform.height), (320.0, 120.0));
+    }
+
+    #[test]
+    fn off_screen_elements_are_culled() {
+        let mut engine = PigmoraEngine::headless();
+        engine.resize(800, 600);
+        let visible_id = engine.add_shape("rect", 100.0, 100.0).unwrap();
+        engine.add_shape("rect", 50_000.0, 50_000.0).unwrap();
+        engine.collect_rects();
+        let visible = engine.document.get_element_transform(visible_id).unwrap();
+        assert_eq!(engine.render_shapes.len(), 1);
+        assert_eq!(engine.render_shapes[0].rect, transform_rect(&visible));
+    }
+
+    #[test]
+    fn hiding_a_layer_keeps_other_colors() {
+        let mut engine = PigmoraEngine::headless();
+        engine.resize(800, 600);
+        let mut ids = vec![engine.add_shape("rect", 0.0, 0.0).unwrap()];
+        let layer_id = engine.document.add_layer("Layer 2");
+        engine.document.active_layer_id = layer_id;
+        ids.push(engine.add_shape("rect", 200.0, 0.0).unwrap());
+        ids.push(engine.add_shape("ellipse", 400.0, 0.0).unwrap());
+        let fills = [[0.9, 0.1, 0.1, 1.0], [0.1, 0.8, 0.2, 1.0], [0.2, 0.3, 0.9, 1.0]];
+        for (&id, [r, g, b, a]) in ids.iter().zip(fills) {
+            if let Some(Element {
+                data: ElementData::Shape(shape),
+                ..
+            }) = engine.document.get_element_by_id_mut(id)
+            {
+                shape.fill = Some(crate::elements::Fill {
+                    color: Color::new(r, g, b, a),
+                });
+            }
+        }
+        let colors = |engine: &PigmoraEngine| -> Vec<[f32; 4]> {
+            engine.render_shapes.iter().map(|shape| shape.color).collect()
+        };
+
+        engine.collect_rects();
+        assert_eq!(colors(&engine), fills);
+
+        engine.document.layers[0].visible = false;
+        engine.collect_rects();
+        assert_eq!(colors(&engine), fills[1..]);
+    }
+
+    #[test]
+    fn read_only_blocks_add_shape() {
+        let mut engine = PigmoraEngine::headless();
+        engine.set_read_only(true);
+        assert!(matches!(
+            engine.add_shape("rect", 0.0, 0.0),
+            Err(EngineError::ReadOnly)
+        ));
+        assert!(matches!(
+            engine.set_element_anchor(1, "nowhere"),
+            Err(EngineError::ReadOnly)
+        ));
+        assert_eq!(engine.document.layers[0].elements.len(), 0);
+    }
+
+    #[test]
+    fn quiet_adds_keep_the_selection() {
+        let mut engine = PigmoraEngine::headless();
+        let selected = engine.add_shape("rect", 0.0, 0.0).unwrap();
+        for index in 0..100 {
+            engine.add_shape_quiet("ellipse", index as f32, 0.0).unwrap();
+        }
+        assert_eq!(engine.selected_ids(), vec![selected]);
+        assert_eq!(engine.document.layers[0].elements.len(), 101);
+    }
+
+    #[test]
+    fn adding_after_removing_the_active_layer() {
+        let mut engine = PigmoraEngine::headless();
+        let layer_id = engine.document.add_layer("Top");
+        engine.document.active_layer_id = layer_id;
+        engine.add_shape("rect", 0.0, 0.0).unwrap();
+        assert!(engine.remove_layer(layer_id).unwrap());
+        let id = engine.add_shape("ellipse", 10.0, 10.0).unwrap();
+        assert_eq!(engine.document.find_element_location(id), Some((1, 0)));
+    }
+
+    #[test]
+    fn layer_removal_is_undoable() {
+        let mut engine = PigmoraEngine::headless();
+        let first = engine.add_shape("rect", 0.0, 0.0).unwrap();
+        let second = engine.add_shape("rect", 100.0, 0.0).unwrap();
+        engine.selected_ids = vec![first, second];
+        let group_id = engine.group_selected().unwrap();
+        let layer_id = engine.document.add_layer("Top");
+        engine.document.move_element(second, layer_id, None);
+        engine.document.active_layer_id = layer_id;
+        let children = |engine: &PigmoraEngine| {
+            match &engine.document.get_element_by_id(group_id).unwrap().data {
+                ElementData::Group(group) => group.children.clone(),
+                _ => Vec::new(),
+            }
         };
-        if self.document.get_element_transform(element_id).is_none() {
-            self.selected_element_id = self.document.find_first_shape();
+
+        assert!(engine.remove_layer(layer_id).unwrap());
+        assert_eq!(children(&engine), vec![first]);
+        assert_eq!(engine.document.active_layer_id, 1);
+
+        assert!(engine.undo());
+        assert_eq!(engine.document.layers.len(), 2);
+        assert_eq!(engine.document.active_layer_id, layer_id);
+        assert_eq!(children(&engine), vec![first, second]);
+        assert!(engine.document.get_element_by_id(second).is_some());
+
+        assert!(engine.redo());
+        assert_eq!(engine.document.layers.len(), 1);
+        assert_eq!(children(&engine), vec![first]);
+    }
+
+    #[test]
+    fn hsl_round_trips_through_rgba() {
+        for hsl in [
+            [0.0, 1.0, 0.5, 1.0],
+            [120.0, 0.5, 0.25, 0.5],
+            [210.0, 0.8, 0.6, 1.0],
+            [300.0, 0.3, 0.9, 0.0],
+            [45.5, 0.65, 0.4, 0.75],
+        ] {
+            let rgba = hsl_to_rgba(hsl[0], hsl[1], hsl[2], hsl[3]);
+            let back = rgba_to_hsl(rgba[0], rgba[1], rgba[2], rgba[3]);
+            assert!((back[0] - hsl[0]).abs() < 1e-3, "hue {hsl:?} -> {back:?}");
+            for channel in 1..4 {
+                assert!((back[channel] - hsl[channel]).abs() < 1e-5, "{hsl:?} -> {back:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn set_rect_leaves_locked_elements() {
+        let mut engine = PigmoraEngine::headless();
+        let id = engine.add_shape("rect", 10.0, 10.0).unwrap();
+        let before = engine.document.get_element_transform(id).unwrap();
+        assert!(engine.set_element_locked(id, true));
+        engine.set_rect(200.0, 200.0, 40.0, 40.0);
+        assert_eq!(engine.document.get_element_transform(id), Some(before));
+        assert_eq!(engine.selected_ids(), vec![id]);
+    }
+
+    #[test]
+    fn nan_transforms_are_ignored() {
+        let mut engine = PigmoraEngine::headless();
+        let element_id = engine.add_shape("rect", 10.0, 20.0).unwrap();
+        let before = engine.document.get_element_transform(element_id);
+        engine.set_rect(f32::NAN, 0.0, 100.0, 100.0);
+        assert!(!engine.update_selected_transform(0.0, f32::NAN, 100.0, 100.0));
+        assert!(!engine.nudge_selected(f32::NAN, 0.0));
+        assert_eq!(engine.document.get_element_transform(element_id), before);
+    }
+
+    #[test]
+    fn nan_update_values_are_rejected() {
+        let updates = [
+            ElementUpdate {
+                corner_radius: Some(f32::NAN),
+                ..ElementUpdate::default()
+            },
+            ElementUpdate {
+                shadow_blur: Some(f32::INFINITY),
+                ..ElementUpdate::default()
+            },
+            ElementUpdate {
+                crop_width: Some(f32::NAN),
+                ..ElementUpdate::default()
+            },
+            ElementUpdate {
+                width: Some(f32::NAN),
+                ..ElementUpdate::default()
+            },
+        ];
+        for update in &updates {
+            assert!(matches!(
+                validate_update(update),
+                Err(EngineError::InvalidValue(_))
+            ));
         }
+        assert!(validate_update(&ElementUpdate::default()).is_ok());
     }
 }
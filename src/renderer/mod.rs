@@ -1,15 +1,70 @@
+mod text;
+mod texture;
 mod webgl;
 
+use serde::Serialize;
 use wasm_bindgen::JsValue;
 
-#[derive(Clone, Copy, Debug)]
+use crate::document::{BlendMode, Fit};
+use crate::elements::TextAlign;
+
+const MAX_CAPTURE_SIZE: f32 = 4096.0;
+// Above this share of the canvas a full redraw is cheaper than a scissored one.
+const PARTIAL_RENDER_LIMIT: f32 = 0.6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ShapeKind {
     Rect = 0,
     Ellipse = 1,
-    Diamond = 2,
+    Polygon = 2,
+    Checker = 3,
+    Line = 4,
+}
+
+// Low trades looks for frame time: no antialiasing or shadow blur, whole-pixel snapping and
+// redraws limited to the region that changed. Medium keeps antialiasing with a single
+// unblurred shadow pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Quality {
+    Low,
+    Medium,
+    #[default]
+    High,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl Quality {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(Quality::Low),
+            "medium" => Some(Quality::Medium),
+            "high" => Some(Quality::High),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Low => "low",
+            Quality::Medium => "medium",
+            Quality::High => "high",
+        }
+    }
+
+    pub fn antialias(self) -> bool {
+        self != Quality::Low
+    }
+
+    pub fn snaps_to_pixels(self) -> bool {
+        self == Quality::Low
+    }
+
+    pub fn partial_redraw(self) -> bool {
+        self == Quality::Low
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
 pub struct Rect {
     pub x: f32,
     pub y: f32,
@@ -17,46 +72,471 @@ pub struct Rect {
     pub height: f32,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RenderImage {
+    pub source: String,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub crop: Option<[f32; 4]>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct RenderStroke {
+    pub color: [f32; 4],
+    pub width: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RenderText {
+    pub content: String,
+    pub font_family: String,
+    pub font_size: f32,
+    pub color: [f32; 4],
+    pub align: TextAlign,
+    pub line_height: f32,
+    pub letter_spacing: f32,
+    pub stroke: Option<RenderStroke>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct RenderShadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub blur: f32,
+    pub color: [f32; 4],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct RenderShape {
     pub rect: Rect,
+    pub color: [f32; 4],
+    #[serde(rename = "kind")]
     pub shape: ShapeKind,
+    pub sides: u32,
+    pub corner_radius: f32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: f32,
+    pub pivot: [f32; 2],
+    pub stroke: Option<RenderStroke>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<RenderImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<RenderText>,
+    pub shadow: Option<RenderShadow>,
+    pub blend: BlendMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionStyle {
+    pub handle_size: f32,
+    pub outline_color: [f32; 4],
+    pub handle_color: [f32; 4],
+}
+
+impl Default for SelectionStyle {
+    fn default() -> Self {
+        Self {
+            handle_size: 24.0,
+            outline_color: [0.98, 0.94, 0.9, 1.0],
+            handle_color: [0.98, 0.96, 0.93, 1.0],
+        }
+    }
+}
+
+impl SelectionStyle {
+    pub fn handle_rects(&self, selection: &Selection) -> [Rect; 4] {
+        let handle_size = self.handle_size;
+        let handle_half = handle_size * 0.5;
+        selection.corners().map(|(x, y)| Rect {
+            x: x - handle_half,
+            y: y - handle_half,
+            width: handle_size,
+            height: handle_size,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection {
+    pub rect: Rect,
+    pub rotation: f32,
+    pub pivot: [f32; 2],
+}
+
+impl Selection {
+    fn corners(&self) -> [(f32, f32); 4] {
+        self.rect.rotated_corners(self.rotation, self.pivot)
+    }
+
+    fn extent(&self) -> Rect {
+        self.rect.rotated_extent(self.rotation, self.pivot)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backdrop {
+    pub rect: Rect,
+    pub color: [f32; 4],
+    pub image: Option<RenderImage>,
+    pub fit: Fit,
+}
+
+impl Backdrop {
+    pub fn transparent() -> Self {
+        Self {
+            rect: Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 0.0,
+                height: 0.0,
+            },
+            color: [0.0; 4],
+            image: None,
+            fit: Fit::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Overlay<'a> {
+    pub selection: Option<Selection>,
+    pub selection_style: SelectionStyle,
+    pub marquee: Option<Rect>,
+    pub guides: &'a [Rect],
 }
 
 impl Rect {
     pub fn is_valid(&self) -> bool {
         self.width > 0.0 && self.height > 0.0
     }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        let min_x = self.x.min(other.x);
+        let min_y = self.y.min(other.y);
+        let max_x = (self.x + self.width).max(other.x + other.width);
+        let max_y = (self.y + self.height).max(other.y + other.height);
+        Rect {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        }
+    }
+
+    fn rotated_corners(&self, rotation: f32, pivot: [f32; 2]) -> [(f32, f32); 4] {
+        let pivot_x = self.x + self.width * pivot[0];
+        let pivot_y = self.y + self.height * pivot[1];
+        let (sin, cos) = rotation.sin_cos();
+        [
+            (self.x, self.y),
+            (self.x + self.width, self.y),
+            (self.x + self.width, self.y + self.height),
+            (self.x, self.y + self.height),
+        ]
+        .map(|(x, y)| {
+            let (dx, dy) = (x - pivot_x, y - pivot_y);
+            (pivot_x + dx * cos - dy * sin, pivot_y + dx * sin + dy * cos)
+        })
+    }
+
+    fn rotated_extent(&self, rotation: f32, pivot: [f32; 2]) -> Rect {
+        let mut min = (f32::INFINITY, f32::INFINITY);
+        let mut max = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for (x, y) in self.rotated_corners(rotation, pivot) {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+        Rect {
+            x: min.0,
+            y: min.1,
+            width: max.0 - min.0,
+            height: max.1 - min.1,
+        }
+    }
+
+    fn expanded(&self, amount: f32) -> Rect {
+        Rect {
+            x: self.x - amount,
+            y: self.y - amount,
+            width: self.width + amount * 2.0,
+            height: self.height + amount * 2.0,
+        }
+    }
+}
+
+impl RenderShape {
+    fn extent(&self) -> Rect {
+        let extent = self.rect.rotated_extent(self.rotation, self.pivot);
+        match &self.shadow {
+            Some(shadow) => extent.union(
+                &Rect {
+                    x: extent.x + shadow.offset_x,
+                    y: extent.y + shadow.offset_y,
+                    ..extent
+                }
+                .expanded(shadow.blur),
+            ),
+            None => extent,
+        }
+    }
+}
+
+struct Frame {
+    backdrop: Backdrop,
+    shapes: Vec<RenderShape>,
+    selection: Option<Selection>,
+    selection_style: SelectionStyle,
+    marquee: Option<Rect>,
+    guides: Vec<Rect>,
+    view: [f32; 3],
+}
+
+enum Redraw {
+    Full,
+    Region(Rect),
+    Nothing,
 }
 
 pub struct Renderer {
-    webgl: webgl::WebGlRenderer,
+    // Only headless test engines go without a GL context; they never draw.
+    webgl: Option<webgl::WebGlRenderer>,
     width: u32,
     height: u32,
+    view: [f32; 3],
+    partial_render: bool,
+    quality: Quality,
+    previous: Option<Frame>,
+    pending_size: Option<(u32, u32)>,
 }
 
 impl Renderer {
     pub fn new(canvas_id: &str) -> Result<Self, JsValue> {
         let webgl = webgl::WebGlRenderer::new(canvas_id)?;
-        Ok(Self {
+        Ok(Self::with_webgl(Some(webgl)))
+    }
+
+    #[cfg(test)]
+    pub fn headless() -> Self {
+        Self::with_webgl(None)
+    }
+
+    fn with_webgl(webgl: Option<webgl::WebGlRenderer>) -> Self {
+        Self {
             webgl,
             width: 0,
             height: 0,
-        })
+            view: [0.0, 0.0, 1.0],
+            partial_render: false,
+            quality: Quality::High,
+            previous: None,
+            pending_size: None,
+        }
     }
 
+    // The GL viewport follows on the next draw so rapid resizes don't clear a frame each.
     pub fn resize(&mut self, width: u32, height: u32) {
-        self.width = width;
-        self.height = height;
-        self.webgl.resize(width, height);
+        self.pending_size = Some((width, height));
+    }
+
+    pub fn pending_resize(&self) -> bool {
+        self.pending_size.is_some()
     }
 
-    pub fn render(&self, rects: &[RenderShape], selected: Option<Rect>) {
+    fn apply_pending_resize(&mut self) {
+        if let Some((width, height)) = self.pending_size.take() {
+            self.width = width;
+            self.height = height;
+            self.previous = None;
+            if let Some(webgl) = &self.webgl {
+                webgl.resize(width, height);
+            }
+        }
+    }
+
+    pub fn set_on_image_load(&mut self, callback: Option<js_sys::Function>) {
+        if let Some(webgl) = &mut self.webgl {
+            webgl.set_on_image_load(callback);
+        }
+    }
+
+    pub fn has_pending_images(&self) -> bool {
+        self.webgl
+            .as_ref()
+            .is_some_and(|webgl| webgl.has_pending_images())
+    }
+
+    pub fn set_view(&mut self, zoom: f32, pan_x: f32, pan_y: f32) {
+        self.view = [pan_x, pan_y, zoom];
+        if let Some(webgl) = &mut self.webgl {
+            webgl.set_view(zoom, pan_x, pan_y);
+        }
+    }
+
+    pub fn set_transparency_grid(&mut self, enabled: bool) {
+        self.previous = None;
+        if let Some(webgl) = &mut self.webgl {
+            webgl.set_transparency_grid(enabled);
+        }
+    }
+
+    pub fn set_partial_render(&mut self, enabled: bool) {
+        self.partial_render = enabled;
+        self.previous = None;
+    }
+
+    pub fn quality(&self) -> Quality {
+        self.quality
+    }
+
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.quality = quality;
+        self.previous = None;
+        if let Some(webgl) = &mut self.webgl {
+            webgl.set_quality(quality);
+        }
+    }
+
+    pub fn capture_region(
+        &mut self,
+        region: &Rect,
+        scale: f32,
+        rects: &[RenderShape],
+    ) -> Option<(u32, u32, Vec<u8>)> {
+        if !region.is_valid() || !scale.is_finite() || scale <= 0.0 {
+            return None;
+        }
+
+        let scale = scale
+            .min(MAX_CAPTURE_SIZE / region.width)
+            .min(MAX_CAPTURE_SIZE / region.height);
+        let width = (region.width * scale).ceil().max(1.0) as u32;
+        let height = (region.height * scale).ceil().max(1.0) as u32;
+        let view = [-region.x * scale, -region.y * scale, scale];
+        let webgl = self.webgl.as_mut()?;
+        let pixels = webgl.render_offscreen(
+            width,
+            height,
+            view,
+            &Backdrop::transparent(),
+            rects,
+            &Overlay::default(),
+        );
+        webgl.resize(self.width, self.height);
+        Some((width, height, pixels?))
+    }
+
+    pub fn render(&mut self, backdrop: &Backdrop, rects: &[RenderShape], overlay: &Overlay<'_>) {
+        self.apply_pending_resize();
         if self.width == 0 || self.height == 0 {
             return;
         }
 
-        self.webgl
-            .render_scene(self.width, self.height, rects, selected);
+        let partial = self.partial_render || self.quality.partial_redraw();
+        let redraw = if partial {
+            self.plan_redraw(backdrop, rects, overlay)
+        } else {
+            Redraw::Full
+        };
+        let Some(webgl) = &mut self.webgl else {
+            return;
+        };
+        match redraw {
+            Redraw::Nothing => {}
+            Redraw::Full => {
+                webgl.render_scene(self.width, self.height, backdrop, rects, overlay);
+            }
+            Redraw::Region(region) => {
+                webgl.set_scissor(Some(region));
+                webgl.render_scene(self.width, self.height, backdrop, rects, overlay);
+                webgl.set_scissor(None);
+            }
+        }
+        if !partial {
+            return;
+        }
+        self.previous = Some(Frame {
+            backdrop: backdrop.clone(),
+            shapes: rects.to_vec(),
+            selection: overlay.selection,
+            selection_style: overlay.selection_style,
+            marquee: overlay.marquee,
+            guides: overlay.guides.to_vec(),
+            view: self.view,
+        });
+    }
+
+    fn plan_redraw(
+        &self,
+        backdrop: &Backdrop,
+        rects: &[RenderShape],
+        overlay: &Overlay<'_>,
+    ) -> Redraw {
+        let Some(previous) = &self.previous else {
+            return Redraw::Full;
+        };
+        if self.has_pending_images()
+            || previous.view != self.view
+            || previous.backdrop != *backdrop
+            || previous.guides != overlay.guides
+            || previous.selection_style != overlay.selection_style
+            || previous.shapes.len() != rects.len()
+        {
+            return Redraw::Full;
+        }
+
+        let mut region: Option<Rect> = None;
+        let mut include = |rect: Rect| {
+            region = Some(match region {
+                Some(region) => region.union(&rect),
+                None => rect,
+            });
+        };
+        for (before, after) in previous.shapes.iter().zip(rects) {
+            if before != after {
+                include(before.extent());
+                include(after.extent());
+            }
+        }
+        if previous.selection != overlay.selection {
+            // Rotated handles reach past half their size, but never past a full one.
+            let handle_size = overlay.selection_style.handle_size;
+            for selection in [previous.selection, overlay.selection].into_iter().flatten() {
+                include(selection.extent().expanded(handle_size));
+            }
+        }
+        if previous.marquee != overlay.marquee {
+            for rect in [previous.marquee, overlay.marquee].into_iter().flatten() {
+                include(rect);
+            }
+        }
+        let Some(region) = region else {
+            return Redraw::Nothing;
+        };
+
+        let [pan_x, pan_y, zoom] = self.view;
+        let width = self.width as f32;
+        let height = self.height as f32;
+        let min_x = ((region.x * zoom + pan_x).floor() - 2.0).max(0.0);
+        let min_y = ((region.y * zoom + pan_y).floor() - 2.0).max(0.0);
+        let max_x = ((region.x + region.width) * zoom + pan_x).ceil() + 2.0;
+        let max_y = ((region.y + region.height) * zoom + pan_y).ceil() + 2.0;
+        let screen = Rect {
+            x: min_x,
+            y: min_y,
+            width: max_x.min(width) - min_x,
+            height: max_y.min(height) - min_y,
+        };
+        if !screen.is_valid() {
+            return Redraw::Nothing;
+        }
+        if !screen.width.is_finite()
+            || !screen.height.is_finite()
+            || screen.width * screen.height > width * height * PARTIAL_RENDER_LIMIT
+        {
+            return Redraw::Full;
+        }
+        Redraw::Region(screen)
     }
 }
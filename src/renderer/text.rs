@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, WebGl2RenderingContext, WebGlTexture};
+
+use crate::elements::TextAlign;
+
+use super::{Rect, RenderText};
+
+const MAX_TEXT_SIZE: f32 = 4096.0;
+
+struct TextEntry {
+    texture: WebGlTexture,
+    used: bool,
+}
+
+pub struct TextCache {
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+    entries: HashMap<String, TextEntry>,
+}
+
+impl TextCache {
+    pub fn new(document: &web_sys::Document) -> Result<Self, JsValue> {
+        let canvas = document
+            .create_element("canvas")?
+            .dyn_into::<HtmlCanvasElement>()?;
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("2D context not available"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        Ok(Self {
+            canvas,
+            context,
+            entries: HashMap::new(),
+        })
+    }
+
+    pub fn get(
+        &mut self,
+        gl: &WebGl2RenderingContext,
+        rect: &Rect,
+        text: &RenderText,
+    ) -> Option<&WebGlTexture> {
+        let width = rect.width.ceil().clamp(1.0, MAX_TEXT_SIZE) as u32;
+        let height = rect.height.ceil().clamp(1.0, MAX_TEXT_SIZE) as u32;
+        let key = format!("{width}x{height}:{text:?}");
+        if !self.entries.contains_key(&key) {
+            self.rasterize(width, height, text).ok()?;
+            let texture = self.upload(gl)?;
+            self.entries.insert(
+                key.clone(),
+                TextEntry {
+                    texture,
+                    used: false,
+                },
+            );
+        }
+        let entry = self.entries.get_mut(&key)?;
+        entry.used = true;
+        Some(&entry.texture)
+    }
+
+    pub fn prune(&mut self, gl: &WebGl2RenderingContext) {
+        self.entries.retain(|_, entry| {
+            if !entry.used {
+                gl.delete_texture(Some(&entry.texture));
+            }
+            std::mem::take(&mut entry.used)
+        });
+    }
+
+    fn rasterize(&self, width: u32, height: u32, text: &RenderText) -> Result<(), JsValue> {
+        self.canvas.set_width(width);
+        self.canvas.set_height(height);
+        let context = &self.context;
+        context.set_font(&format!("{}px {}", text.font_size, text.font_family));
+        context.set_text_baseline("top");
+        context.set_text_align("left");
+        context.set_fill_style_str(&css_color(text.color));
+        if let Some(stroke) = &text.stroke {
+            context.set_stroke_style_str(&css_color(stroke.color));
+            context.set_line_width(stroke.width as f64);
+            context.set_line_join("round");
+        }
+
+        let line_advance = (text.font_size * text.line_height) as f64;
+        let letter_spacing = text.letter_spacing as f64;
+        let mut glyph = [0u8; 4];
+        for (index, line) in text.content.lines().enumerate() {
+            let y = index as f64 * line_advance;
+            let line_width = self.measure_line(line, letter_spacing)?;
+            let mut x = match text.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => (width as f64 - line_width) * 0.5,
+                TextAlign::Right => width as f64 - line_width,
+            };
+            if letter_spacing == 0.0 {
+                self.draw_run(line, x, y, text.stroke.is_some())?;
+                continue;
+            }
+            for ch in line.chars() {
+                let run = ch.encode_utf8(&mut glyph);
+                self.draw_run(run, x, y, text.stroke.is_some())?;
+                x += context.measure_text(run)?.width() + letter_spacing;
+            }
+        }
+        Ok(())
+    }
+
+    fn measure_line(&self, line: &str, letter_spacing: f64) -> Result<f64, JsValue> {
+        let width = self.context.measure_text(line)?.width();
+        let gaps = line.chars().count().saturating_sub(1) as f64;
+        Ok(width + gaps * letter_spacing)
+    }
+
+    fn draw_run(&self, run: &str, x: f64, y: f64, stroke: bool) -> Result<(), JsValue> {
+        if stroke {
+            self.context.stroke_text(run, x, y)?;
+        }
+        self.context.fill_text(run, x, y)
+    }
+
+    fn upload(&self, gl: &WebGl2RenderingContext) -> Option<WebGlTexture> {
+        let texture = gl.create_texture()?;
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_WRAP_S,
+            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_WRAP_T,
+            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+            WebGl2RenderingContext::LINEAR as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+            WebGl2RenderingContext::LINEAR as i32,
+        );
+        let uploaded = gl.tex_image_2d_with_u32_and_u32_and_html_canvas_element(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            WebGl2RenderingContext::RGBA as i32,
+            WebGl2RenderingContext::RGBA,
+            WebGl2RenderingContext::UNSIGNED_BYTE,
+            &self.canvas,
+        );
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        match uploaded {
+            Ok(()) => Some(texture),
+            Err(_) => {
+                gl.delete_texture(Some(&texture));
+                None
+            }
+        }
+    }
+}
+
+fn css_color(color: [f32; 4]) -> String {
+    let [r, g, b, a] = color;
+    format!(
+        "rgba({}, {}, {}, {})",
+        (r.clamp(0.0, 1.0) * 255.0).round(),
+        (g.clamp(0.0, 1.0) * 255.0).round(),
+        (b.clamp(0.0, 1.0) * 255.0).round(),
+        a.clamp(0.0, 1.0)
+    )
+}
@@ -0,0 +1,125 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use web_sys::{HtmlImageElement, WebGl2RenderingContext, WebGlTexture};
+
+struct TextureEntry {
+    image: HtmlImageElement,
+    texture: Option<WebGlTexture>,
+    _on_load: Closure<dyn FnMut()>,
+}
+
+pub struct TextureCache {
+    entries: HashMap<String, TextureEntry>,
+    on_load: Rc<RefCell<Option<js_sys::Function>>>,
+    pending_uploads: Rc<Cell<bool>>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            on_load: Rc::new(RefCell::new(None)),
+            pending_uploads: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn has_pending_uploads(&self) -> bool {
+        self.pending_uploads.get()
+    }
+
+    pub fn clear_pending_uploads(&self) {
+        self.pending_uploads.set(false);
+    }
+
+    pub fn set_on_load(&mut self, callback: Option<js_sys::Function>) {
+        *self.on_load.borrow_mut() = callback;
+    }
+
+    pub fn get(&mut self, gl: &WebGl2RenderingContext, source: &str) -> Option<&WebGlTexture> {
+        if source.is_empty() {
+            return None;
+        }
+        if !self.entries.contains_key(source) {
+            let entry = self.load(source)?;
+            self.entries.insert(source.to_string(), entry);
+        }
+
+        let entry = self.entries.get_mut(source)?;
+        if entry.texture.is_none() && entry.image.complete() && entry.image.natural_width() > 0 {
+            entry.texture = Self::upload(gl, &entry.image);
+        }
+        entry.texture.as_ref()
+    }
+
+    pub fn size(&self, source: &str) -> Option<(f32, f32)> {
+        let image = &self.entries.get(source)?.image;
+        if !image.complete() || image.natural_width() == 0 || image.natural_height() == 0 {
+            return None;
+        }
+        Some((image.natural_width() as f32, image.natural_height() as f32))
+    }
+
+    fn load(&self, source: &str) -> Option<TextureEntry> {
+        let image = HtmlImageElement::new().ok()?;
+        let on_load = Rc::clone(&self.on_load);
+        let pending_uploads = Rc::clone(&self.pending_uploads);
+        let closure = Closure::<dyn FnMut()>::new(move || {
+            pending_uploads.set(true);
+            if let Some(callback) = on_load.borrow().as_ref() {
+                let _ = callback.call0(&JsValue::NULL);
+            }
+        });
+        image.set_onload(Some(closure.as_ref().unchecked_ref()));
+        image.set_cross_origin(Some("anonymous"));
+        image.set_src(source);
+        Some(TextureEntry {
+            image,
+            texture: None,
+            _on_load: closure,
+        })
+    }
+
+    fn upload(gl: &WebGl2RenderingContext, image: &HtmlImageElement) -> Option<WebGlTexture> {
+        let texture = gl.create_texture()?;
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_WRAP_S,
+            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_WRAP_T,
+            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+            WebGl2RenderingContext::LINEAR as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+            WebGl2RenderingContext::LINEAR as i32,
+        );
+        let uploaded = gl.tex_image_2d_with_u32_and_u32_and_html_image_element(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            WebGl2RenderingContext::RGBA as i32,
+            WebGl2RenderingContext::RGBA,
+            WebGl2RenderingContext::UNSIGNED_BYTE,
+            image,
+        );
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        match uploaded {
+            Ok(()) => Some(texture),
+            Err(_) => {
+                gl.delete_texture(Some(&texture));
+                None
+            }
+        }
+    }
+}
@@ -5,7 +5,246 @@ use web_sys::{
     WebGlVertexArrayObject,
 };
 
-use super::{Rect, RenderShape, ShapeKind};
+use super::text::TextCache;
+use super::texture::TextureCache;
+use crate::document::{BlendMode, Fit, line_reach};
+
+use super::{
+    Backdrop, Overlay, Quality, Rect, RenderImage, RenderShadow, RenderShape, RenderText,
+    Selection, SelectionStyle, ShapeKind,
+};
+
+const ATTRIBUTES: [&str; 7] = [
+    "a_position",
+    "a_origin",
+    "a_size",
+    "a_color",
+    "a_shape",
+    "a_flip",
+    "a_transform",
+];
+const INSTANCE_COMPONENTS: [i32; 6] = [2, 2, 4, 4, 2, 3];
+const INSTANCE_FLOATS: usize = 17;
+const INSTANCE_STRIDE: i32 = (INSTANCE_FLOATS * 4) as i32;
+const SHADOW_BLUR_STEPS: usize = 4;
+const LINE_AA_MARGIN: f32 = 1.0;
+const CLEAR_COLOR: [f32; 4] = [0.06, 0.07, 0.08, 1.0];
+const MARQUEE_FILL: [f32; 4] = [0.35, 0.6, 1.0, 0.15];
+const MARQUEE_OUTLINE: [f32; 4] = [0.35, 0.6, 1.0, 0.9];
+const GUIDE_COLOR: [f32; 4] = [0.0, 0.75, 1.0, 0.8];
+
+struct Instance {
+    rect: Rect,
+    color: [f32; 4],
+    shape: ShapeKind,
+    sides: u32,
+    corner_radius: f32,
+    flip_x: bool,
+    flip_y: bool,
+    rotation: f32,
+    pivot: [f32; 2],
+    stroke_width: f32,
+}
+
+impl Instance {
+    fn plain(rect: Rect, color: [f32; 4]) -> Self {
+        Self {
+            rect,
+            color,
+            shape: ShapeKind::Rect,
+            sides: 4,
+            corner_radius: 0.0,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.0,
+            pivot: [0.5, 0.5],
+            stroke_width: 0.0,
+        }
+    }
+
+    // Lines reach past their box by half the stroke, so the quad grows to cover the caps.
+    fn inflate_line(&mut self) {
+        let margin = line_reach(self.stroke_width) + LINE_AA_MARGIN;
+        let rect = self.rect;
+        let width = rect.width + margin * 2.0;
+        let height = rect.height + margin * 2.0;
+        self.pivot = [
+            (self.pivot[0] * rect.width + margin) / width,
+            (self.pivot[1] * rect.height + margin) / height,
+        ];
+        self.rect = Rect {
+            x: rect.x - margin,
+            y: rect.y - margin,
+            width,
+            height,
+        };
+    }
+
+    fn write(&self, out: &mut Vec<f32>) {
+        out.extend_from_slice(&[
+            self.rect.x,
+            self.rect.y,
+            self.rect.width,
+            self.rect.height,
+        ]);
+        let [r, g, b, a] = self.color;
+        out.extend_from_slice(&[srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]);
+        out.extend_from_slice(&[
+            self.shape as i32 as f32,
+            self.sides as f32,
+            self.corner_radius,
+            self.stroke_width,
+            if self.flip_x { -1.0 } else { 1.0 },
+            if self.flip_y { -1.0 } else { 1.0 },
+            self.rotation,
+            self.pivot[0],
+            self.pivot[1],
+        ]);
+    }
+}
+
+// GL rows start at the bottom and colors are premultiplied by alpha.
+fn unpremultiply_rows(pixels: &[u8], stride: usize) -> Vec<u8> {
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks_exact(stride).rev() {
+        flipped.extend_from_slice(row);
+    }
+    for pixel in flipped.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha > 0 && alpha < 255 {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    }
+    flipped
+}
+
+// The shader outputs premultiplied color, so every mode scales the source by alpha once.
+fn blend_factors(blend: BlendMode) -> (u32, u32) {
+    match blend {
+        BlendMode::Normal => (
+            WebGl2RenderingContext::ONE,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        ),
+        BlendMode::Multiply => (
+            WebGl2RenderingContext::DST_COLOR,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        ),
+        BlendMode::Screen => (
+            WebGl2RenderingContext::ONE,
+            WebGl2RenderingContext::ONE_MINUS_SRC_COLOR,
+        ),
+        BlendMode::Add => (WebGl2RenderingContext::ONE, WebGl2RenderingContext::ONE),
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+enum OverlayPass {
+    Outline { start: usize },
+    Fill { start: usize, count: usize },
+}
+
+#[derive(Clone, Copy)]
+struct UvTransform {
+    rect: [f32; 4],
+    repeat: [f32; 2],
+}
+
+impl UvTransform {
+    const IDENTITY: UvTransform = UvTransform {
+        rect: [0.0, 0.0, 1.0, 1.0],
+        repeat: [1.0, 1.0],
+    };
+}
+
+fn fit_backdrop(
+    canvas: Rect,
+    image_width: f32,
+    image_height: f32,
+    fit: Fit,
+) -> (Rect, UvTransform) {
+    match fit {
+        Fit::Stretch => (canvas, UvTransform::IDENTITY),
+        Fit::Fit => {
+            let scale = (canvas.width / image_width).min(canvas.height / image_height);
+            let width = image_width * scale;
+            let height = image_height * scale;
+            let rect = Rect {
+                x: canvas.x + (canvas.width - width) * 0.5,
+                y: canvas.y + (canvas.height - height) * 0.5,
+                width,
+                height,
+            };
+            (rect, UvTransform::IDENTITY)
+        }
+        Fit::Fill => {
+            let scale = (canvas.width / image_width).max(canvas.height / image_height);
+            let visible_x = canvas.width / (image_width * scale);
+            let visible_y = canvas.height / (image_height * scale);
+            let uv = UvTransform {
+                rect: [
+                    (1.0 - visible_x) * 0.5,
+                    (1.0 - visible_y) * 0.5,
+                    visible_x,
+                    visible_y,
+                ],
+                repeat: [1.0, 1.0],
+            };
+            (canvas, uv)
+        }
+        Fit::Tile => {
+            let uv = UvTransform {
+                rect: [0.0, 0.0, 1.0, 1.0],
+                repeat: [canvas.width / image_width, canvas.height / image_height],
+            };
+            (canvas, uv)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum BatchSource<'a> {
+    Image(&'a RenderImage, UvTransform),
+    Text(&'a Rect, &'a RenderText),
+}
+
+struct Batch<'a> {
+    start: usize,
+    count: usize,
+    source: Option<BatchSource<'a>>,
+    blend: BlendMode,
+}
+
+fn push_batch<'a>(
+    batches: &mut Vec<Batch<'a>>,
+    index: usize,
+    source: Option<BatchSource<'a>>,
+    blend: BlendMode,
+) {
+    if source.is_none()
+        && let Some(last) = batches.last_mut()
+        && last.source.is_none()
+        && last.blend == blend
+        && last.start + last.count == index
+    {
+        last.count += 1;
+        return;
+    }
+    batches.push(Batch {
+        start: index,
+        count: 1,
+        source,
+        blend,
+    });
+}
 
 pub struct WebGlRenderer {
     gl: WebGl2RenderingContext,
@@ -15,11 +254,23 @@ pub struct WebGlRenderer {
     vertex_buffer: WebGlBuffer,
     #[allow(dead_code)]
     index_buffer: WebGlBuffer,
+    instance_buffer: WebGlBuffer,
+    instance_data: Vec<f32>,
     uniform_resolution: Option<WebGlUniformLocation>,
-    uniform_origin: Option<WebGlUniformLocation>,
-    uniform_size: Option<WebGlUniformLocation>,
-    uniform_color: Option<WebGlUniformLocation>,
-    uniform_shape: Option<WebGlUniformLocation>,
+    uniform_use_texture: Option<WebGlUniformLocation>,
+    uniform_brightness: Option<WebGlUniformLocation>,
+    uniform_contrast: Option<WebGlUniformLocation>,
+    uniform_saturation: Option<WebGlUniformLocation>,
+    uniform_uv_rect: Option<WebGlUniformLocation>,
+    uniform_uv_repeat: Option<WebGlUniformLocation>,
+    textures: TextureCache,
+    text_cache: TextCache,
+    transparency_grid: bool,
+    view: [f32; 3],
+    uniform_view: Option<WebGlUniformLocation>,
+    quality: Quality,
+    uniform_snap: Option<WebGlUniformLocation>,
+    uniform_antialias: Option<WebGlUniformLocation>,
 }
 
 impl WebGlRenderer {
@@ -32,6 +283,7 @@ impl WebGlRenderer {
             .get_element_by_id(canvas_id)
             .ok_or_else(|| JsValue::from_str("Canvas element not found"))?
             .dyn_into::<HtmlCanvasElement>()?;
+        let text_cache = TextCache::new(&document)?;
 
         let context_options = js_sys::Object::new();
         js_sys::Reflect::set(&context_options, &JsValue::from_str("alpha"), &JsValue::FALSE)?;
@@ -53,7 +305,7 @@ impl WebGlRenderer {
         js_sys::Reflect::set(
             &context_options,
             &JsValue::from_str("preserveDrawingBuffer"),
-            &JsValue::FALSE,
+            &JsValue::TRUE,
         )?;
         js_sys::Reflect::set(
             &context_options,
@@ -73,6 +325,9 @@ impl WebGlRenderer {
         let index_buffer = gl
             .create_buffer()
             .ok_or_else(|| JsValue::from_str("Failed to create index buffer"))?;
+        let instance_buffer = gl
+            .create_buffer()
+            .ok_or_else(|| JsValue::from_str("Failed to create instance buffer"))?;
 
         let vao = gl
             .create_vertex_array()
@@ -102,6 +357,12 @@ impl WebGlRenderer {
             0,
         );
 
+        gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&instance_buffer));
+        for location in 1..ATTRIBUTES.len() as u32 {
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_divisor(location, 1);
+        }
+
         let indices: [u16; 4] = [0, 1, 3, 2];
         let index_array = js_sys::Uint16Array::from(indices.as_ref());
         gl.bind_buffer(
@@ -119,14 +380,29 @@ impl WebGlRenderer {
 
         gl.use_program(Some(&program));
         let uniform_resolution = gl.get_uniform_location(&program, "u_resolution");
-        let uniform_origin = gl.get_uniform_location(&program, "u_origin");
-        let uniform_size = gl.get_uniform_location(&program, "u_size");
-        let uniform_color = gl.get_uniform_location(&program, "u_color");
-        let uniform_shape = gl.get_uniform_location(&program, "u_shape");
+        let uniform_use_texture = gl.get_uniform_location(&program, "u_use_texture");
+        let uniform_brightness = gl.get_uniform_location(&program, "u_brightness");
+        let uniform_contrast = gl.get_uniform_location(&program, "u_contrast");
+        let uniform_saturation = gl.get_uniform_location(&program, "u_saturation");
+        let uniform_view = gl.get_uniform_location(&program, "u_view");
+        let uniform_snap = gl.get_uniform_location(&program, "u_snap");
+        let uniform_antialias = gl.get_uniform_location(&program, "u_antialias");
+        let uniform_uv_rect = gl.get_uniform_location(&program, "u_uv_rect");
+        let uniform_uv_repeat = gl.get_uniform_location(&program, "u_uv_repeat");
+        let uniform_texture = gl.get_uniform_location(&program, "u_texture");
+        gl.uniform1i(uniform_texture.as_ref(), 0);
 
         gl.disable(WebGl2RenderingContext::DEPTH_TEST);
         gl.disable(WebGl2RenderingContext::CULL_FACE);
-        gl.clear_color(0.06, 0.07, 0.08, 1.0);
+        gl.enable(WebGl2RenderingContext::BLEND);
+        gl.blend_func_separate(
+            WebGl2RenderingContext::ONE,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+            WebGl2RenderingContext::ONE,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+        let [r, g, b, a] = CLEAR_COLOR;
+        gl.clear_color(r, g, b, a);
 
         Ok(Self {
             gl,
@@ -134,25 +410,74 @@ impl WebGlRenderer {
             vao,
             vertex_buffer,
             index_buffer,
+            instance_buffer,
+            instance_data: Vec::new(),
             uniform_resolution,
-            uniform_origin,
-            uniform_size,
-            uniform_color,
-            uniform_shape,
+            uniform_use_texture,
+            uniform_brightness,
+            uniform_contrast,
+            uniform_saturation,
+            uniform_uv_rect,
+            uniform_uv_repeat,
+            textures: TextureCache::new(),
+            text_cache,
+            transparency_grid: false,
+            view: [0.0, 0.0, 1.0],
+            uniform_view,
+            quality: Quality::High,
+            uniform_snap,
+            uniform_antialias,
         })
     }
 
+    pub fn set_on_image_load(&mut self, callback: Option<js_sys::Function>) {
+        self.textures.set_on_load(callback);
+    }
+
+    pub fn has_pending_images(&self) -> bool {
+        self.textures.has_pending_uploads()
+    }
+
+    pub fn set_view(&mut self, zoom: f32, pan_x: f32, pan_y: f32) {
+        self.view = [pan_x, pan_y, zoom];
+    }
+
+    pub fn set_scissor(&self, region: Option<Rect>) {
+        match region {
+            Some(region) => {
+                let canvas_height = self.gl.drawing_buffer_height();
+                self.gl.enable(WebGl2RenderingContext::SCISSOR_TEST);
+                self.gl.scissor(
+                    region.x as i32,
+                    canvas_height - (region.y + region.height) as i32,
+                    region.width as i32,
+                    region.height as i32,
+                );
+            }
+            None => self.gl.disable(WebGl2RenderingContext::SCISSOR_TEST),
+        }
+    }
+
+    pub fn set_transparency_grid(&mut self, enabled: bool) {
+        self.transparency_grid = enabled;
+    }
+
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.quality = quality;
+    }
+
     pub fn resize(&self, width: u32, height: u32) {
         self.gl
             .viewport(0, 0, width as i32, height as i32);
     }
 
     pub fn render_scene(
-        &self,
+        &mut self,
         width: u32,
         height: u32,
+        backdrop: &Backdrop,
         rects: &[RenderShape],
-        selected: Option<Rect>,
+        overlay: &Overlay<'_>,
     ) {
         self.gl
             .clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
@@ -161,29 +486,215 @@ impl WebGlRenderer {
             return;
         }
 
+        let mut data = std::mem::take(&mut self.instance_data);
+        data.clear();
+        let mut batches = Vec::new();
+        let mut count = 0;
+
+        if backdrop.rect.is_valid() {
+            if self.transparency_grid && backdrop.color[3] < 1.0 {
+                Instance {
+                    shape: ShapeKind::Checker,
+                    ..Instance::plain(backdrop.rect, [1.0; 4])
+                }
+                .write(&mut data);
+                push_batch(&mut batches, count, None, BlendMode::Normal);
+                count += 1;
+            }
+            Instance::plain(backdrop.rect, backdrop.color).write(&mut data);
+            push_batch(&mut batches, count, None, BlendMode::Normal);
+            count += 1;
+            if let Some(image) = &backdrop.image {
+                self.textures.get(&self.gl, &image.source);
+                if let Some((image_width, image_height)) = self.textures.size(&image.source) {
+                    let (rect, uv) =
+                        fit_backdrop(backdrop.rect, image_width, image_height, backdrop.fit);
+                    Instance::plain(rect, [1.0; 4]).write(&mut data);
+                    push_batch(
+                        &mut batches,
+                        count,
+                        Some(BatchSource::Image(image, uv)),
+                        BlendMode::Normal,
+                    );
+                    count += 1;
+                }
+            }
+        }
+
+        count = Self::push_shapes(&mut data, &mut batches, count, rects, self.quality);
+
+        let mut overlay_passes = Vec::new();
+        if !overlay.guides.is_empty() {
+            for guide in overlay.guides {
+                Instance::plain(*guide, GUIDE_COLOR).write(&mut data);
+            }
+            overlay_passes.push(OverlayPass::Fill {
+                start: count,
+                count: overlay.guides.len(),
+            });
+            count += overlay.guides.len();
+        }
+        if let Some(selection) = overlay.selection
+            && selection.rect.is_valid()
+        {
+            Self::push_selection(&mut data, &selection, &overlay.selection_style);
+            overlay_passes.push(OverlayPass::Outline { start: count });
+            overlay_passes.push(OverlayPass::Fill {
+                start: count + 1,
+                count: 4,
+            });
+            count += 5;
+        }
+        if let Some(rect) = overlay.marquee
+            && rect.is_valid()
+        {
+            Instance::plain(rect, MARQUEE_FILL).write(&mut data);
+            Instance::plain(rect, MARQUEE_OUTLINE).write(&mut data);
+            overlay_passes.push(OverlayPass::Fill {
+                start: count,
+                count: 1,
+            });
+            overlay_passes.push(OverlayPass::Outline { start: count + 1 });
+        }
+
         self.gl.use_program(Some(&self.program));
         self.gl.bind_vertex_array(Some(&self.vao));
-
         self.set_resolution(width, height);
+        let [pan_x, pan_y, zoom] = self.view;
+        self.gl
+            .uniform3f(self.uniform_view.as_ref(), pan_x, pan_y, zoom);
+        let snap = if self.quality.snaps_to_pixels() { 1.0 } else { 0.0 };
+        self.gl.uniform1f(self.uniform_snap.as_ref(), snap);
+        self.gl
+            .uniform1i(self.uniform_antialias.as_ref(), self.quality.antialias() as i32);
+        self.textures.clear_pending_uploads();
 
-        for (index, shape) in rects.iter().enumerate() {
-            if !shape.rect.is_valid() {
-                continue;
+        let instance_array = js_sys::Float32Array::from(data.as_slice());
+        self.gl.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&self.instance_buffer),
+        );
+        self.gl.buffer_data_with_array_buffer_view(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            &instance_array,
+            WebGl2RenderingContext::DYNAMIC_DRAW,
+        );
+
+        let mut blend = BlendMode::Normal;
+        for batch in &batches {
+            if batch.blend != blend {
+                self.set_blend_mode(batch.blend);
+                blend = batch.blend;
             }
-            self.set_rect_uniforms(&shape.rect);
-            self.set_shape(shape.shape);
-            let tint = (index % 4) as f32 * 0.04;
-            self.set_color(0.86 - tint, 0.42 + tint, 0.25 + tint, 1.0);
-            self.gl
-                .draw_arrays(WebGl2RenderingContext::TRIANGLE_STRIP, 0, 4);
+            self.bind_instances(batch.start);
+            let textured = self.bind_source(batch.source);
+            self.gl.draw_arrays_instanced(
+                WebGl2RenderingContext::TRIANGLE_STRIP,
+                0,
+                4,
+                batch.count as i32,
+            );
+            if textured {
+                self.unbind_texture();
+            }
+        }
+        if blend != BlendMode::Normal {
+            self.set_blend_mode(BlendMode::Normal);
         }
+        self.text_cache.prune(&self.gl);
 
-        if let Some(rect) = selected {
-            if rect.is_valid() {
-                self.draw_selection_outline(&rect);
-            }
+        for pass in &overlay_passes {
+            self.draw_overlay_pass(pass);
         }
+
         self.gl.bind_vertex_array(None);
+        self.gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, None);
+        self.instance_data = data;
+    }
+
+    pub fn render_offscreen(
+        &mut self,
+        width: u32,
+        height: u32,
+        view: [f32; 3],
+        backdrop: &Backdrop,
+        rects: &[RenderShape],
+        overlay: &Overlay<'_>,
+    ) -> Option<Vec<u8>> {
+        let texture = self.gl.create_texture()?;
+        self.gl
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+        let allocated = self
+            .gl
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RGBA as i32,
+                width as i32,
+                height as i32,
+                0,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                None,
+            );
+        self.gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        let framebuffer = match (allocated, self.gl.create_framebuffer()) {
+            (Ok(()), Some(framebuffer)) => framebuffer,
+            _ => {
+                self.gl.delete_texture(Some(&texture));
+                return None;
+            }
+        };
+        self.gl
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+        self.gl.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        self.gl.viewport(0, 0, width as i32, height as i32);
+        let pixels = self.capture(width, height, view, backdrop, rects, overlay);
+        self.gl
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.gl.delete_framebuffer(Some(&framebuffer));
+        self.gl.delete_texture(Some(&texture));
+        pixels
+    }
+
+    pub fn capture(
+        &mut self,
+        width: u32,
+        height: u32,
+        view: [f32; 3],
+        backdrop: &Backdrop,
+        rects: &[RenderShape],
+        overlay: &Overlay<'_>,
+    ) -> Option<Vec<u8>> {
+        let view = std::mem::replace(&mut self.view, view);
+        let transparency_grid = std::mem::take(&mut self.transparency_grid);
+        self.gl.clear_color(0.0, 0.0, 0.0, 0.0);
+        self.render_scene(width, height, backdrop, rects, overlay);
+        let [r, g, b, a] = CLEAR_COLOR;
+        self.gl.clear_color(r, g, b, a);
+        self.view = view;
+        self.transparency_grid = transparency_grid;
+
+        let stride = width as usize * 4;
+        let mut pixels = vec![0u8; stride * height as usize];
+        self.gl
+            .read_pixels_with_opt_u8_array(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                Some(&mut pixels),
+            )
+            .ok()?;
+        Some(unpremultiply_rows(&pixels, stride))
     }
 
     fn set_resolution(&self, width: u32, height: u32) {
@@ -196,83 +707,372 @@ impl WebGlRenderer {
         }
     }
 
-    fn set_rect_uniforms(&self, rect: &Rect) {
-        if let Some(origin_loc) = &self.uniform_origin {
-            self.gl
-                .uniform2f(Some(origin_loc), rect.x, rect.y);
+    fn bind_instances(&self, start: usize) {
+        let mut offset = start as i32 * INSTANCE_STRIDE;
+        for (index, components) in INSTANCE_COMPONENTS.iter().enumerate() {
+            self.gl.vertex_attrib_pointer_with_i32(
+                index as u32 + 1,
+                *components,
+                WebGl2RenderingContext::FLOAT,
+                false,
+                INSTANCE_STRIDE,
+                offset,
+            );
+            offset += components * 4;
         }
+    }
 
-        if let Some(size_loc) = &self.uniform_size {
-            self.gl
-                .uniform2f(Some(size_loc), rect.width, rect.height);
+    fn set_blend_mode(&self, blend: BlendMode) {
+        let (src, dst) = blend_factors(blend);
+        self.gl.blend_func_separate(
+            src,
+            dst,
+            WebGl2RenderingContext::ONE,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+    }
+
+    // Consecutive untextured shapes with the same blend mode share one instanced draw.
+    fn push_shapes<'a>(
+        data: &mut Vec<f32>,
+        batches: &mut Vec<Batch<'a>>,
+        mut count: usize,
+        rects: &'a [RenderShape],
+        quality: Quality,
+    ) -> usize {
+        for shape in rects {
+            let mut instance = Instance {
+                rect: shape.rect,
+                color: shape.color,
+                shape: shape.shape,
+                sides: shape.sides,
+                corner_radius: shape.corner_radius,
+                flip_x: shape.flip_x,
+                flip_y: shape.flip_y,
+                rotation: shape.rotation,
+                pivot: shape.pivot,
+                stroke_width: 0.0,
+            };
+            // A line is only its stroke; without one it draws a hairline in the fill color.
+            if shape.shape == ShapeKind::Line {
+                match &shape.stroke {
+                    Some(stroke) => {
+                        instance.color = stroke.color;
+                        instance.stroke_width = stroke.width;
+                    }
+                    None => instance.stroke_width = 1.0,
+                }
+                instance.inflate_line();
+            }
+            if !instance.rect.is_valid() {
+                continue;
+            }
+            if let Some(shadow) = &shape.shadow {
+                let steps = match quality {
+                    Quality::Low => 0,
+                    Quality::Medium => 1,
+                    Quality::High if shadow.blur > 0.0 => SHADOW_BLUR_STEPS,
+                    Quality::High => 1,
+                };
+                count = Self::push_shadow(data, batches, count, &instance, shadow, steps);
+            }
+            instance.write(data);
+            let source = match (&shape.image, &shape.text) {
+                (Some(image), _) => {
+                    let uv = match image.crop {
+                        Some(rect) => UvTransform {
+                            rect,
+                            repeat: [1.0, 1.0],
+                        },
+                        None => UvTransform::IDENTITY,
+                    };
+                    Some(BatchSource::Image(image, uv))
+                }
+                (None, Some(text)) => Some(BatchSource::Text(&shape.rect, text)),
+                (None, None) => None,
+            };
+            push_batch(batches, count, source, shape.blend);
+            count += 1;
+            if let Some(stroke) = &shape.stroke
+                && stroke.width > 0.0
+                && shape.shape != ShapeKind::Line
+            {
+                Instance {
+                    color: stroke.color,
+                    stroke_width: stroke.width,
+                    ..instance
+                }
+                .write(data);
+                push_batch(batches, count, None, shape.blend);
+                count += 1;
+            }
         }
+        count
     }
 
-    fn set_color(&self, r: f32, g: f32, b: f32, a: f32) {
-        if let Some(color_loc) = &self.uniform_color {
-            self.gl
-                .uniform4f(Some(color_loc), r, g, b, a);
+    fn push_shadow<'a>(
+        data: &mut Vec<f32>,
+        batches: &mut Vec<Batch<'a>>,
+        mut count: usize,
+        instance: &Instance,
+        shadow: &RenderShadow,
+        steps: usize,
+    ) -> usize {
+        let [r, g, b, a] = shadow.color;
+        let step_alpha = a / steps as f32;
+        for step in 0..steps {
+            let spread = shadow.blur * (1.0 - step as f32 / steps as f32) * 0.5;
+            let rect = &instance.rect;
+            let width = rect.width + spread * 2.0;
+            let height = rect.height + spread * 2.0;
+            let [pivot_x, pivot_y] = instance.pivot;
+            Instance {
+                rect: Rect {
+                    x: rect.x + shadow.offset_x - spread,
+                    y: rect.y + shadow.offset_y - spread,
+                    width,
+                    height,
+                },
+                color: [r, g, b, step_alpha],
+                pivot: [
+                    (pivot_x * rect.width + spread) / width,
+                    (pivot_y * rect.height + spread) / height,
+                ],
+                ..*instance
+            }
+            .write(data);
+            push_batch(batches, count, None, BlendMode::Normal);
+            count += 1;
         }
+        count
     }
 
-    fn set_shape(&self, shape: ShapeKind) {
-        if let Some(shape_loc) = &self.uniform_shape {
-            self.gl.uniform1i(Some(shape_loc), shape as i32);
+    fn push_selection(data: &mut Vec<f32>, selection: &Selection, style: &SelectionStyle) {
+        Instance {
+            rotation: selection.rotation,
+            pivot: selection.pivot,
+            ..Instance::plain(selection.rect, style.outline_color)
+        }
+        .write(data);
+        for handle in style.handle_rects(selection) {
+            Instance {
+                rotation: selection.rotation,
+                ..Instance::plain(handle, style.handle_color)
+            }
+            .write(data);
         }
     }
 
-    fn draw_selection_outline(&self, rect: &Rect) {
-        self.set_rect_uniforms(rect);
-        self.set_color(0.98, 0.94, 0.9, 1.0);
-        self.gl.line_width(1.0);
-        self.gl.draw_elements_with_i32(
-            WebGl2RenderingContext::LINE_LOOP,
-            4,
-            WebGl2RenderingContext::UNSIGNED_SHORT,
-            0,
-        );
+    fn bind_source(&mut self, source: Option<BatchSource>) -> bool {
+        let (texture, filters, uv) = match source {
+            Some(BatchSource::Image(image, uv)) => (
+                self.textures.get(&self.gl, &image.source).cloned(),
+                [image.brightness, image.contrast, image.saturation],
+                uv,
+            ),
+            Some(BatchSource::Text(rect, text)) => (
+                self.text_cache.get(&self.gl, rect, text).cloned(),
+                [1.0; 3],
+                UvTransform::IDENTITY,
+            ),
+            None => return false,
+        };
+        let texture = match texture {
+            Some(texture) => texture,
+            None => return false,
+        };
+        let [brightness, contrast, saturation] = filters;
+        self.gl.active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.gl
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+        if let Some(use_texture_loc) = &self.uniform_use_texture {
+            self.gl.uniform1i(Some(use_texture_loc), 1);
+        }
+        if let Some(brightness_loc) = &self.uniform_brightness {
+            self.gl.uniform1f(Some(brightness_loc), brightness);
+        }
+        if let Some(contrast_loc) = &self.uniform_contrast {
+            self.gl.uniform1f(Some(contrast_loc), contrast);
+        }
+        if let Some(saturation_loc) = &self.uniform_saturation {
+            self.gl.uniform1f(Some(saturation_loc), saturation);
+        }
+        let [u, v, uv_width, uv_height] = uv.rect;
+        self.gl
+            .uniform4f(self.uniform_uv_rect.as_ref(), u, v, uv_width, uv_height);
+        self.gl
+            .uniform2f(self.uniform_uv_repeat.as_ref(), uv.repeat[0], uv.repeat[1]);
+        true
+    }
 
-        let handle_size = 24.0;
-        let handle_half = handle_size * 0.5;
-        let handles = [
-            Rect {
-                x: rect.x - handle_half,
-                y: rect.y - handle_half,
-                width: handle_size,
-                height: handle_size,
-            },
-            Rect {
-                x: rect.x + rect.width - handle_half,
-                y: rect.y - handle_half,
-                width: handle_size,
-                height: handle_size,
-            },
-            Rect {
-                x: rect.x + rect.width - handle_half,
-                y: rect.y + rect.height - handle_half,
-                width: handle_size,
-                height: handle_size,
-            },
-            Rect {
-                x: rect.x - handle_half,
-                y: rect.y + rect.height - handle_half,
-                width: handle_size,
-                height: handle_size,
-            },
-        ];
+    fn unbind_texture(&self) {
+        if let Some(use_texture_loc) = &self.uniform_use_texture {
+            self.gl.uniform1i(Some(use_texture_loc), 0);
+        }
+        self.gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+    }
 
-        self.set_color(0.98, 0.96, 0.93, 1.0);
-        for handle in &handles {
-            self.set_rect_uniforms(handle);
-            self.gl
-                .draw_arrays(WebGl2RenderingContext::TRIANGLE_STRIP, 0, 4);
+    fn draw_overlay_pass(&self, pass: &OverlayPass) {
+        match *pass {
+            OverlayPass::Outline { start } => {
+                self.bind_instances(start);
+                self.gl.line_width(1.0);
+                self.gl.draw_elements_instanced_with_i32(
+                    WebGl2RenderingContext::LINE_LOOP,
+                    4,
+                    WebGl2RenderingContext::UNSIGNED_SHORT,
+                    0,
+                    1,
+                );
+            }
+            OverlayPass::Fill { start, count } => {
+                self.bind_instances(start);
+                self.gl.draw_arrays_instanced(
+                    WebGl2RenderingContext::TRIANGLE_STRIP,
+                    0,
+                    4,
+                    count as i32,
+                );
+            }
         }
     }
 
     fn create_program(gl: &WebGl2RenderingContext) -> Result<WebGlProgram, JsValue> {
-        let vertex_source = "#version 300 es\nin vec2 a_position;\nuniform vec2 u_origin;\nuniform vec2 u_size;\nuniform vec2 u_resolution;\nout vec2 v_local;\nvoid main() {\n  v_local = a_position;\n  vec2 position = u_origin + (a_position * u_size);\n  vec2 zeroToOne = position / u_resolution;\n  vec2 zeroToTwo = zeroToOne * 2.0;\n  vec2 clip = zeroToTwo - 1.0;\n  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);\n}\n";
+        let vertex_source = "#version 300 es
+in vec2 a_position;
+in vec2 a_origin;
+in vec2 a_size;
+in vec4 a_color;
+in vec4 a_shape;
+in vec2 a_flip;
+in vec3 a_transform;
+uniform vec2 u_resolution;
+uniform vec3 u_view;
+uniform float u_snap;
+out vec2 v_local;
+flat out vec2 v_size;
+flat out vec4 v_color;
+flat out int v_shape;
+flat out int v_sides;
+flat out float v_corner_radius;
+flat out float v_stroke;
+void main() {
+  v_local = (a_position - 0.5) * a_flip + 0.5;
+  v_size = a_size;
+  v_color = a_color;
+  v_shape = int(a_shape.x + 0.5);
+  v_sides = int(a_shape.y + 0.5);
+  v_corner_radius = a_shape.z;
+  v_stroke = a_shape.w;
+  vec2 pivot = a_transform.yz * a_size;
+  vec2 offset = a_position * a_size - pivot;
+  float s = sin(a_transform.x);
+  float c = cos(a_transform.x);
+  vec2 rotated = vec2(offset.x * c - offset.y * s, offset.x * s + offset.y * c) + pivot;
+  vec2 position = (a_origin + rotated) * u_view.z + u_view.xy;
+  position = mix(position, floor(position + 0.5), u_snap);
+  vec2 zeroToOne = position / u_resolution;
+  vec2 zeroToTwo = zeroToOne * 2.0;
+  vec2 clip = zeroToTwo - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+";
 
-        let fragment_source = "#version 300 es\nprecision mediump float;\nuniform vec4 u_color;\nuniform int u_shape;\nin vec2 v_local;\nout vec4 out_color;\nvoid main() {\n  vec2 centered = v_local - vec2(0.5);\n  if (u_shape == 1) {\n    vec2 norm = centered / vec2(0.5);\n    if (dot(norm, norm) > 1.0) {\n      discard;\n    }\n  } else if (u_shape == 2) {\n    float diamond = abs(centered.x) + abs(centered.y);\n    if (diamond > 0.5) {\n      discard;\n    }\n  }\n  out_color = u_color;\n}\n";
+        let fragment_source = "#version 300 es
+precision mediump float;
+uniform int u_use_texture;
+uniform int u_antialias;
+uniform sampler2D u_texture;
+uniform float u_brightness;
+uniform float u_contrast;
+uniform float u_saturation;
+uniform vec4 u_uv_rect;
+uniform vec2 u_uv_repeat;
+in vec2 v_local;
+flat in highp vec2 v_size;
+flat in vec4 v_color;
+flat in int v_shape;
+flat in int v_sides;
+flat in float v_corner_radius;
+flat in float v_stroke;
+out vec4 out_color;
+const float CHECKER_CELL = 8.0;
+const float LINE_AA_MARGIN = 1.0;
+vec3 linear_to_srgb(vec3 rgb) {
+  vec3 low = rgb * 12.92;
+  vec3 high = 1.055 * pow(rgb, vec3(1.0 / 2.4)) - 0.055;
+  return mix(high, low, vec3(lessThanEqual(rgb, vec3(0.0031308))));
+}
+// Signed distance to the shape edge, positive outside; ellipses and polygons are approximated.
+highp float edge_distance(highp vec2 p, highp vec2 half_size, float corner_radius) {
+  if (any(lessThanEqual(half_size, vec2(0.0)))) {
+    return 1.0e4;
+  }
+  highp float scale = min(half_size.x, half_size.y);
+  if (v_shape == 1) {
+    return (length(p / half_size) - 1.0) * scale;
+  }
+  if (v_shape == 2) {
+    vec2 norm = p / half_size;
+    float sector = 6.28318530718 / float(max(v_sides, 3));
+    float angle = mod(atan(norm.x, -norm.y), sector) - sector * 0.5;
+    return (length(norm) * cos(angle) - cos(sector * 0.5)) * scale;
+  }
+  highp float radius = clamp(corner_radius, 0.0, scale);
+  highp vec2 q = abs(p) - half_size + radius;
+  return length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - radius;
+}
+float coverage(highp float edge) {
+  if (u_antialias == 0) {
+    return edge > 0.0 ? 0.0 : 1.0;
+  }
+  return clamp(0.5 - edge / max(fwidth(edge), 0.0001), 0.0, 1.0);
+}
+void main() {
+  if (v_shape == 3) {
+    vec2 cell = floor(v_local * v_size / CHECKER_CELL);
+    float odd = mod(cell.x + cell.y, 2.0);
+    out_color = vec4(mix(vec3(1.0), vec3(0.8), odd), 1.0);
+    return;
+  }
+  highp vec2 p = (v_local - vec2(0.5)) * v_size;
+  highp vec2 half_size = v_size * 0.5;
+  float alpha;
+  if (v_shape == 4) {
+    // The quad is grown past the line's box, so the segment spans the box without the margin.
+    float reach = max(v_stroke, 1.0) * 0.5;
+    vec2 line_half = max(half_size - (reach + LINE_AA_MARGIN), vec2(0.0));
+    vec2 span = line_half * 2.0;
+    float t = clamp(dot(p + line_half, span) / max(dot(span, span), 0.0001), 0.0, 1.0);
+    alpha = coverage(length(p + line_half - span * t) - reach);
+  } else {
+    alpha = coverage(edge_distance(p, half_size, v_corner_radius));
+  }
+  // Strokes are drawn inside the shape edge, so the inset silhouette is cut out.
+  if (v_shape != 4 && v_stroke > 0.0) {
+    alpha *= 1.0 - coverage(edge_distance(p, half_size - v_stroke, v_corner_radius - v_stroke));
+  }
+  if (alpha <= 0.0) {
+    discard;
+  }
+  if (u_use_texture == 1) {
+    vec2 tiled = v_local * u_uv_repeat;
+    if (u_uv_repeat != vec2(1.0)) {
+      tiled = fract(tiled);
+    }
+    vec4 texel = texture(u_texture, u_uv_rect.xy + tiled * u_uv_rect.zw);
+    vec3 rgb = texel.rgb * u_brightness;
+    rgb = (rgb - 0.5) * u_contrast + 0.5;
+    float luma = dot(rgb, vec3(0.2126, 0.7152, 0.0722));
+    rgb = mix(vec3(luma), rgb, u_saturation);
+    out_color = vec4(clamp(rgb, 0.0, 1.0), texel.a * v_color.a);
+  } else {
+    out_color = vec4(linear_to_srgb(v_color.rgb), v_color.a);
+  }
+  out_color.a *= alpha;
+  out_color.rgb *= out_color.a;
+}
+";
 
         let vertex_shader = Self::compile_shader(
             gl,
@@ -288,7 +1088,9 @@ impl WebGlRenderer {
         let program = gl
             .create_program()
             .ok_or_else(|| JsValue::from_str("Failed to create program"))?;
-        gl.bind_attrib_location(&program, 0, "a_position");
+        for (location, name) in ATTRIBUTES.iter().enumerate() {
+            gl.bind_attrib_location(&program, location as u32, name);
+        }
         gl.attach_shader(&program, &vertex_shader);
         gl.attach_shader(&program, &fragment_shader);
         gl.link_program(&program);
@@ -332,3 +1134,165 @@ impl WebGlRenderer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::RenderStroke;
+
+    fn shape(index: usize) -> RenderShape {
+        RenderShape {
+            rect: Rect {
+                x: (index % 40) as f32 * 20.0,
+                y: (index / 40) as f32 * 20.0,
+                width: 16.0,
+                height: 16.0,
+            },
+            color: [0.86, 0.42, 0.25, 1.0],
+            shape: ShapeKind::Rect,
+            sides: 4,
+            corner_radius: 0.0,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.0,
+            pivot: [0.5, 0.5],
+            stroke: None,
+            image: None,
+            text: None,
+            shadow: None,
+            blend: BlendMode::Normal,
+        }
+    }
+
+    #[test]
+    fn mid_gray_converts_to_linear() {
+        assert!((srgb_to_linear(0.5) - 0.214_041).abs() < 1e-5);
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+
+        let rect = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        };
+        let mut data = Vec::new();
+        Instance::plain(rect, [0.5, 0.5, 0.5, 0.5]).write(&mut data);
+        assert!((data[4] - 0.214_041).abs() < 1e-5);
+        assert_eq!(data[7], 0.5);
+    }
+
+    #[test]
+    fn uncovered_pixels_read_back_transparent() {
+        // Two rows as GL returns them: a half-covered red pixel over a cleared one.
+        let pixels = [0, 0, 0, 0, 128, 0, 0, 128];
+        let rows = unpremultiply_rows(&pixels, 4);
+        assert_eq!(&rows[..4], &[255, 0, 0, 128]);
+        assert_eq!(&rows[4..], &[0, 0, 0, 0]);
+    }
+
+    fn blend_factor(factor: u32, src: f32, alpha: f32, dst: f32) -> f32 {
+        match factor {
+            WebGl2RenderingContext::ONE => 1.0,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA => 1.0 - alpha,
+            WebGl2RenderingContext::ONE_MINUS_SRC_COLOR => 1.0 - src,
+            WebGl2RenderingContext::DST_COLOR => dst,
+            _ => panic!("unexpected blend factor {factor}"),
+        }
+    }
+
+    #[test]
+    fn blend_modes_respect_half_alpha() {
+        let (color, alpha, dst) = (0.8, 0.5, 0.4);
+        let src = color * alpha;
+        for (blend, blended) in [
+            (BlendMode::Normal, color),
+            (BlendMode::Multiply, color * dst),
+            (BlendMode::Screen, color + dst - color * dst),
+            (BlendMode::Add, color + dst),
+        ] {
+            let (src_factor, dst_factor) = blend_factors(blend);
+            let result = src * blend_factor(src_factor, src, alpha, dst)
+                + dst * blend_factor(dst_factor, src, alpha, dst);
+            let expected = dst + alpha * (blended - dst);
+            assert!((result - expected).abs() < 1e-6, "{blend:?}: {result} != {expected}");
+        }
+    }
+
+    #[test]
+    fn flat_lines_grow_to_their_stroke() {
+        let line = RenderShape {
+            rect: Rect {
+                x: 10.0,
+                y: 50.0,
+                width: 100.0,
+                height: 0.0,
+            },
+            shape: ShapeKind::Line,
+            stroke: Some(RenderStroke {
+                color: [0.0, 0.0, 0.0, 1.0],
+                width: 6.0,
+            }),
+            ..shape(0)
+        };
+        let mut data = Vec::new();
+        let mut batches = Vec::new();
+        let count = WebGlRenderer::push_shapes(&mut data, &mut batches, 0, &[line], Quality::High);
+        assert_eq!(count, 1);
+        let margin = 3.0 + LINE_AA_MARGIN;
+        assert_eq!(&data[..4], &[10.0 - margin, 50.0 - margin, 100.0 + margin * 2.0, margin * 2.0]);
+        assert_eq!(&data[15..17], &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn rotation_reaches_instances_and_handles() {
+        let rotation = std::f32::consts::FRAC_PI_2;
+        let rotated = RenderShape {
+            rotation,
+            pivot: [0.0, 1.0],
+            ..shape(0)
+        };
+        let mut data = Vec::new();
+        WebGlRenderer::push_shapes(&mut data, &mut Vec::new(), 0, &[rotated], Quality::High);
+        assert_eq!(&data[14..17], &[rotation, 0.0, 1.0]);
+
+        let selection = Selection {
+            rect: Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 40.0,
+                height: 20.0,
+            },
+            rotation,
+            pivot: [0.5, 0.5],
+        };
+        let handle = SelectionStyle::default().handle_rects(&selection)[0];
+        let half = SelectionStyle::default().handle_size * 0.5;
+        assert!((handle.x + half - 30.0).abs() < 1e-4);
+        assert!((handle.y + half + 10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn quality_tiers_trade_antialiasing_for_partial_redraws() {
+        assert!(!Quality::Low.antialias());
+        assert!(Quality::Low.snaps_to_pixels());
+        assert!(Quality::Low.partial_redraw());
+        for quality in [Quality::Medium, Quality::High] {
+            assert!(quality.antialias());
+            assert!(!quality.snaps_to_pixels());
+            assert!(!quality.partial_redraw());
+        }
+    }
+
+    #[test]
+    fn thousand_shapes_share_one_draw_call() {
+        let shapes: Vec<RenderShape> = (0..1000).map(shape).collect();
+        let mut data = Vec::new();
+        let mut batches = Vec::new();
+        let count = WebGlRenderer::push_shapes(&mut data, &mut batches, 0, &shapes, Quality::High);
+        assert_eq!(count, 1000);
+        assert_eq!(data.len(), 1000 * INSTANCE_FLOATS);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].count, 1000);
+    }
+}
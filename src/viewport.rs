@@ -0,0 +1,107 @@
+use serde::Serialize;
+
+use crate::document::Bounds;
+
+pub const MIN_ZOOM: f32 = 0.05;
+pub const MAX_ZOOM: f32 = 32.0;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Viewport {
+    pub zoom: f32,
+    pub pan_x: f32,
+    pub pan_y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            zoom: 1.0,
+            pan_x: 0.0,
+            pan_y: 0.0,
+            width,
+            height,
+        }
+    }
+
+    pub fn set(&mut self, zoom: f32, pan_x: f32, pan_y: f32) -> bool {
+        if !zoom.is_finite() || !pan_x.is_finite() || !pan_y.is_finite() {
+            return false;
+        }
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.pan_x = pan_x;
+        self.pan_y = pan_y;
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.zoom = 1.0;
+        self.pan_x = 0.0;
+        self.pan_y = 0.0;
+    }
+
+    pub fn zoom_at(&mut self, screen_x: f32, screen_y: f32, factor: f32) -> bool {
+        if !screen_x.is_finite() || !screen_y.is_finite() || !factor.is_finite() || factor <= 0.0 {
+            return false;
+        }
+        let (doc_x, doc_y) = self.screen_to_document(screen_x, screen_y);
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        if zoom == self.zoom {
+            return false;
+        }
+        self.zoom = zoom;
+        self.pan_x = screen_x - doc_x * zoom;
+        self.pan_y = screen_y - doc_y * zoom;
+        true
+    }
+
+    pub fn scroll_into_view(&mut self, bounds: &Bounds, margin: f32) -> bool {
+        let width = bounds.width * self.zoom;
+        let height = bounds.height * self.zoom;
+        if width + margin * 2.0 > self.width || height + margin * 2.0 > self.height {
+            self.fit(bounds, margin);
+            return true;
+        }
+        let dx = scroll_offset(bounds.x * self.zoom + self.pan_x, width, self.width, margin);
+        let dy = scroll_offset(bounds.y * self.zoom + self.pan_y, height, self.height, margin);
+        self.pan_x += dx;
+        self.pan_y += dy;
+        dx != 0.0 || dy != 0.0
+    }
+
+    pub fn screen_to_document(&self, x: f32, y: f32) -> (f32, f32) {
+        ((x - self.pan_x) / self.zoom, (y - self.pan_y) / self.zoom)
+    }
+
+    pub fn visible_bounds(&self) -> Bounds {
+        let (x, y) = self.screen_to_document(0.0, 0.0);
+        Bounds {
+            x,
+            y,
+            width: self.width / self.zoom,
+            height: self.height / self.zoom,
+        }
+    }
+
+    pub fn fit(&mut self, bounds: &Bounds, padding: f32) {
+        let available_width = (self.width - padding * 2.0).max(1.0);
+        let available_height = (self.height - padding * 2.0).max(1.0);
+        let zoom = (available_width / bounds.width.max(1.0))
+            .min(available_height / bounds.height.max(1.0))
+            .clamp(MIN_ZOOM, MAX_ZOOM);
+        self.zoom = zoom;
+        self.pan_x = self.width * 0.5 - (bounds.x + bounds.width * 0.5) * zoom;
+        self.pan_y = self.height * 0.5 - (bounds.y + bounds.height * 0.5) * zoom;
+    }
+}
+
+fn scroll_offset(start: f32, size: f32, extent: f32, margin: f32) -> f32 {
+    if start < margin {
+        margin - start
+    } else if start + size > extent - margin {
+        extent - margin - (start + size)
+    } else {
+        0.0
+    }
+}